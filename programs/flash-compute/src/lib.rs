@@ -3,7 +3,12 @@ use solana_program::pubkey;
 use anchor_spl::token::Mint;
 use flash_read::states::*;
 use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
-use flash_read::math;
+use flash_read::valuation::PoolValuation;
+use flash_read::position::{PositionContext, PositionHealth};
+use flash_read::swap::{self, SwapQuote};
+use flash_read::liquidity::{self, LiquidityQuote};
+use flash_read::oracle::{self, ResolvedPrice};
+use flash_read::liquidation::{self, LiquidationPrices};
 
 
 declare_id!("Fcmp5ZQ1wR5swZ87aRQyHfUiHYxrfrRVhCWrV2yYA6QG");
@@ -22,283 +27,288 @@ pub mod flash_compute {
         ctx: Context<GetPoolTokenPrices>,
     ) -> Result<(u64, u64)> {
         let pool = &ctx.accounts.pool;
-        let mut custody_prices: Vec<OraclePrice> = Vec::new();
-        let mut pool_equity: u64 = 0;
+        let (custodies, custody_prices) = get_pool_custodies(
+            pool,
+            ctx.remaining_accounts,
+            solana_program::sysvar::clock::Clock::get()?.unix_timestamp,
+        )?;
+
+        let markets = get_pool_markets(pool, ctx.remaining_accounts)?;
+        let (sflp_price_usd, flp_price) = PoolValuation::new(pool, &custodies, &markets)?
+            .get_pool_token_prices(custody_prices.as_slice(), ctx.accounts.lp_token_mint.supply)?;
+
+        msg!("SFLP Price: {}, FLP Price: {}", sflp_price_usd, flp_price);
+
+        Ok((sflp_price_usd, flp_price))
+    }
+
+    pub fn get_realtime_pool_token_prices(
+        ctx: Context<GetRealtimePoolTokenPrices>,
+    ) -> Result<(u64, u64)> {
+        let pool = &ctx.accounts.pool;
+        let mut custodies: Vec<Custody> = Vec::with_capacity(pool.custodies.len());
+        let mut custody_prices: Vec<OraclePrice> = Vec::with_capacity(pool.custodies.len());
 
-        // Computing the raw AUM of the pool
         for (idx, &custody) in pool.custodies.iter().enumerate() {
 
             require_keys_eq!(ctx.remaining_accounts[idx].key(), custody);
-            let custody = Box::new(Account::<Custody>::try_from(&ctx.remaining_accounts[idx])?);
+            let custody = Account::<Custody>::try_from(&ctx.remaining_accounts[idx])?.into_inner();
             let oracle_idx = idx + pool.custodies.len();  
             if oracle_idx >= ctx.remaining_accounts.len() {
                 return Err(ProgramError::NotEnoughAccountKeys.into());
             }
             require_keys_eq!(ctx.remaining_accounts[oracle_idx].key(), custody.oracle.ext_oracle_account);
 
-            let pyth_price = Account::<PriceUpdateV2>::try_from(&ctx.remaining_accounts[oracle_idx])?;
+            let price = Account::<CustomOracle>::try_from(&ctx.remaining_accounts[oracle_idx])?;
 
             custody_prices.push(OraclePrice {
-                    price: pyth_price.price_message.price as u64,
-                    exponent: pyth_price.price_message.exponent as i32,
+                    price: price.price,
+                    exponent: price.expo,
             });
-
-            let token_amount_usd =
-                custody_prices[idx].get_asset_amount_usd(custody.assets.owned, custody.decimals)?;
-            pool_equity = math::checked_add(pool_equity, token_amount_usd)?;
-
-        }
-
-        pool_equity = pool_equity.saturating_sub(math::checked_add(pool.fees_obligation_usd, pool.rebate_obligation_usd)?);
-
-        // Computing the unrealsied PnL pending against the pool
-
-        
-        for (idx, &market) in pool.markets.iter().enumerate() {
-            require_keys_eq!(ctx.remaining_accounts[(pool.custodies.len() * 2) + idx].key(), market);
-            let market = Box::new(Account::<Market>::try_from(&ctx.remaining_accounts[(pool.custodies.len() * 2) + idx])?);
-            let target_custody_id = pool.get_custody_id(&market.target_custody)?;
-            let collateral_custody_id = pool.get_custody_id(&market.collateral_custody)?;
-            // Get the collective position against the pool
-            let position = Box::new(market.get_collective_position()?);
-            pool_equity = pool_equity.saturating_sub(position.collateral_usd);
-            let exit_price = custody_prices[target_custody_id];
-            pool_equity = if market.side == Side::Short {
-                if exit_price < position.entry_price {
-                    // Shorts are in collective profit
-                     pool_equity.saturating_sub(std::cmp::min(
-                        position.entry_price.checked_sub(&exit_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
-                        custody_prices[collateral_custody_id].get_asset_amount_usd(position.locked_amount, position.locked_decimals)?
-                    ))
-                } else {
-                    // Shorts are in collective loss
-                    pool_equity.checked_add(std::cmp::min(
-                        exit_price.checked_sub(&position.entry_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
-                        position.collateral_usd
-                    )).unwrap()
-                }
-            } else {
-                if exit_price > position.entry_price {
-                    // Longs are in collective profit
-                    pool_equity.saturating_sub(std::cmp::min(
-                        exit_price.checked_sub(&position.entry_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
-                        custody_prices[collateral_custody_id].get_asset_amount_usd(position.locked_amount, position.locked_decimals)?
-                    ))
-                } else {
-                    // Longs are in collective loss
-                    pool_equity.checked_add(std::cmp::min(
-                        position.entry_price.checked_sub(&exit_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
-                        position.collateral_usd
-                    )).unwrap()
-                }
-
-            };
+            custodies.push(custody);
         }
 
-        let lp_supply = ctx.accounts.lp_token_mint.supply;
+        let markets = get_pool_markets(pool, ctx.remaining_accounts)?;
+        let (sflp_price_usd, flp_price) = PoolValuation::new(pool, &custodies, &markets)?
+            .get_pool_token_prices(custody_prices.as_slice(), ctx.accounts.lp_token_mint.supply)?;
 
-        let sflp_price_usd = math::checked_decimal_div(
-            math::checked_as_u64(pool_equity)?,
-            -(Perpetuals::USD_DECIMALS as i32),
-            lp_supply,
-            -(Perpetuals::LP_DECIMALS as i32),
-            -(Perpetuals::USD_DECIMALS as i32),
-        )?;
+        msg!("SFLP Price: {}, FLP Price: {}", sflp_price_usd, flp_price);
 
-        let compounding_factor = math::checked_decimal_div(
-            pool.compounding_stats.active_amount, 
-            -(Perpetuals::LP_DECIMALS as i32), 
-            pool.compounding_stats.total_supply,
-            -(Perpetuals::LP_DECIMALS as i32),
-            -(Perpetuals::LP_DECIMALS as i32),
-        )?;
+        Ok((sflp_price_usd, flp_price))
+    }
 
-        let flp_price = math::checked_decimal_mul(
-            sflp_price_usd,
-            -(Perpetuals::USD_DECIMALS as i32),
-            compounding_factor,
-            -(Perpetuals::LP_DECIMALS as i32),
-            -(Perpetuals::USD_DECIMALS as i32),
+    pub fn get_liquidation_price(
+        ctx: Context<GetLiquidationPrice>,
+    ) -> Result<LiquidationPrices> {
+        let position = &ctx.accounts.position;
+        let position_context = PositionContext {
+            pool: &ctx.accounts.pool,
+            market: &ctx.accounts.market,
+            target_custody: &ctx.accounts.target_custody,
+            collateral_custody: &ctx.accounts.collateral_custody,
+        };
+
+        let curtime = solana_program::sysvar::clock::Clock::get()?.unix_timestamp;
+        let target_price = get_pyth_price(&ctx.accounts.target_oracle_account, &ctx.accounts.target_custody, curtime)?;
+        let collateral_price = get_pyth_price(&ctx.accounts.collateral_oracle_account, &ctx.accounts.collateral_custody, curtime)?;
+
+        let prices = liquidation::get_liquidation_prices(
+            &position_context,
+            position,
+            &target_price,
+            &collateral_price,
+            curtime,
         )?;
 
-        msg!("SFLP Price: {}, FLP Price: {}", sflp_price_usd, flp_price);
+        msg!("Price: {}, Liquidation Price: {}, Bankruptcy Price: {}", target_price.price, prices.liquidation_price.price, prices.bankruptcy_price.price);
 
-        Ok((sflp_price_usd, flp_price))
+        Ok(prices)
     }
 
-    pub fn get_realtime_pool_token_prices(
-        ctx: Context<GetRealtimePoolTokenPrices>,
+    pub fn get_position_pnl(
+        ctx: Context<GetLiquidationPrice>,
     ) -> Result<(u64, u64)> {
+        let position = &ctx.accounts.position;
         let pool = &ctx.accounts.pool;
-        let mut custody_prices: Vec<OraclePrice> = Vec::new();
-        let mut pool_equity: u64 = 0;
+        let market = &ctx.accounts.market;
+        let target_custody = &ctx.accounts.target_custody;
 
-        // Computing the raw AUM of the pool
-        for (idx, &custody) in pool.custodies.iter().enumerate() {
+        let curtime = solana_program::sysvar::clock::Clock::get()?.unix_timestamp;
+        let (min_price, max_price) = fetch_pyth_price(&ctx.accounts.target_oracle_account, target_custody, curtime)?;
+        let collateral_price = get_pyth_price(&ctx.accounts.collateral_oracle_account, &ctx.accounts.collateral_custody, curtime)?;
 
-            require_keys_eq!(ctx.remaining_accounts[idx].key(), custody);
-            let custody = Box::new(Account::<Custody>::try_from(&ctx.remaining_accounts[idx])?);
-            let oracle_idx = idx + pool.custodies.len();  
-            if oracle_idx >= ctx.remaining_accounts.len() {
-                return Err(ProgramError::NotEnoughAccountKeys.into());
-            }
-            require_keys_eq!(ctx.remaining_accounts[oracle_idx].key(), custody.oracle.ext_oracle_account);
+        let exit_price = pool.get_exit_price(
+            &min_price,
+            &max_price,
+            market.side,
+            target_custody.get_trade_spread(position.size_usd)?,
+        )?;
 
-            let price = Account::<CustomOracle>::try_from(&ctx.remaining_accounts[oracle_idx])?;
+        let (profit_usd, loss_usd) = position.get_pnl_usd(
+            &exit_price,
+            market.side,
+            &collateral_price,
+            market.max_payoff_bps,
+        )?;
 
-            custody_prices.push(OraclePrice {
-                    price: price.price as u64,
-                    exponent: price.expo as i32,
-            });
+        msg!("Profit: {}, Loss: {}", profit_usd, loss_usd);
 
-            let token_amount_usd =
-                custody_prices[idx].get_asset_amount_usd(custody.assets.owned, custody.decimals)?;
-            pool_equity = math::checked_add(pool_equity, token_amount_usd)?;
+        Ok((profit_usd, loss_usd))
+    }
 
-        }
+    pub fn get_position_health(
+        ctx: Context<GetLiquidationPrice>,
+    ) -> Result<PositionHealth> {
+        let position = &ctx.accounts.position;
+        let position_context = PositionContext {
+            pool: &ctx.accounts.pool,
+            market: &ctx.accounts.market,
+            target_custody: &ctx.accounts.target_custody,
+            collateral_custody: &ctx.accounts.collateral_custody,
+        };
+
+        let curtime = solana_program::sysvar::clock::Clock::get()?.unix_timestamp;
+        let (min_price, max_price) = fetch_pyth_price(&ctx.accounts.target_oracle_account, position_context.target_custody, curtime)?;
+        let collateral_price = get_pyth_price(&ctx.accounts.collateral_oracle_account, position_context.collateral_custody, curtime)?;
+        let exit_price = position_context.get_exit_price(position, &min_price, &max_price)?;
+
+        let health = position_context.get_position_health(
+            position,
+            &exit_price,
+            &collateral_price,
+            curtime,
+        )?;
 
-        pool_equity = pool_equity.saturating_sub(math::checked_add(pool.fees_obligation_usd, pool.rebate_obligation_usd)?);
-
-        // Computing the unrealsied PnL pending against the pool
-
-        
-        for (idx, &market) in pool.markets.iter().enumerate() {
-            require_keys_eq!(ctx.remaining_accounts[(pool.custodies.len() * 2) + idx].key(), market);
-            let market = Box::new(Account::<Market>::try_from(&ctx.remaining_accounts[(pool.custodies.len() * 2) + idx])?);
-            let target_custody_id = pool.get_custody_id(&market.target_custody)?;
-            let collateral_custody_id = pool.get_custody_id(&market.collateral_custody)?;
-            // Get the collective position against the pool
-            let position = Box::new(market.get_collective_position()?);
-            pool_equity = pool_equity.saturating_sub(position.collateral_usd);
-            let exit_price = custody_prices[target_custody_id];
-            pool_equity = if market.side == Side::Short {
-                if exit_price < position.entry_price {
-                    // Shorts are in collective profit
-                     pool_equity.saturating_sub(std::cmp::min(
-                        position.entry_price.checked_sub(&exit_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
-                        custody_prices[collateral_custody_id].get_asset_amount_usd(position.locked_amount, position.locked_decimals)?
-                    ))
-                } else {
-                    // Shorts are in collective loss
-                    pool_equity.checked_add(std::cmp::min(
-                        exit_price.checked_sub(&position.entry_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
-                        position.collateral_usd
-                    )).unwrap()
-                }
-            } else {
-                if exit_price > position.entry_price {
-                    // Longs are in collective profit
-                    pool_equity.saturating_sub(std::cmp::min(
-                        exit_price.checked_sub(&position.entry_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
-                        custody_prices[collateral_custody_id].get_asset_amount_usd(position.locked_amount, position.locked_decimals)?
-                    ))
-                } else {
-                    // Longs are in collective loss
-                    pool_equity.checked_add(std::cmp::min(
-                        position.entry_price.checked_sub(&exit_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
-                        position.collateral_usd
-                    )).unwrap()
-                }
-
-            };
-        }
+        msg!("Leverage: {}, Equity: {}", health.leverage, health.equity_usd);
 
-        let lp_supply = ctx.accounts.lp_token_mint.supply;
+        Ok(health)
+    }
 
-        let sflp_price_usd = math::checked_decimal_div(
-            math::checked_as_u64(pool_equity)?,
-            -(Perpetuals::USD_DECIMALS as i32),
-            lp_supply,
-            -(Perpetuals::LP_DECIMALS as i32),
-            -(Perpetuals::USD_DECIMALS as i32),
+    pub fn get_swap_amount_and_fees(
+        ctx: Context<GetSwapAmountAndFees>,
+        amount_in: u64,
+    ) -> Result<SwapQuote> {
+        let pool = &ctx.accounts.pool;
+        let (custodies, custody_prices) = get_pool_custodies(
+            pool,
+            ctx.remaining_accounts,
+            solana_program::sysvar::clock::Clock::get()?.unix_timestamp,
         )?;
 
-        let compounding_factor = math::checked_decimal_div(
-            pool.compounding_stats.active_amount, 
-            -(Perpetuals::LP_DECIMALS as i32), 
-            pool.compounding_stats.total_supply,
-            -(Perpetuals::LP_DECIMALS as i32),
-            -(Perpetuals::LP_DECIMALS as i32),
+        let quote = swap::get_swap_amount_and_fees(
+            pool,
+            &custodies,
+            &custody_prices,
+            pool.get_custody_id(&ctx.accounts.receiving_custody.key())?,
+            pool.get_custody_id(&ctx.accounts.dispensing_custody.key())?,
+            amount_in,
         )?;
 
-        let flp_price = math::checked_decimal_mul(
-            sflp_price_usd,
-            -(Perpetuals::USD_DECIMALS as i32),
-            compounding_factor,
-            -(Perpetuals::LP_DECIMALS as i32),
-            -(Perpetuals::USD_DECIMALS as i32),
+        msg!("Amount out: {}, Fee in: {}, Fee out: {}", quote.amount_out, quote.fee_in, quote.fee_out);
+
+        Ok(quote)
+    }
+
+    pub fn get_add_liquidity_amount_and_fee(
+        ctx: Context<GetLiquidityAmountAndFee>,
+        amount_in: u64,
+        compounding: bool,
+    ) -> Result<LiquidityQuote> {
+        let pool = &ctx.accounts.pool;
+        let (custodies, custody_prices) = get_pool_custodies(
+            pool,
+            ctx.remaining_accounts,
+            solana_program::sysvar::clock::Clock::get()?.unix_timestamp,
         )?;
+        let markets = get_pool_markets(pool, ctx.remaining_accounts)?;
+        let valuation = PoolValuation::new(pool, &custodies, &markets)?;
+        let token_id = pool.get_custody_id(&ctx.accounts.custody.key())?;
+        let lp_supply = ctx.accounts.lp_token_mint.supply;
 
-        msg!("SFLP Price: {}, FLP Price: {}", sflp_price_usd, flp_price);
+        let quote = if compounding {
+            liquidity::get_add_compounding_liquidity_amount_and_fee(&valuation, &custody_prices, lp_supply, token_id, amount_in)?
+        } else {
+            liquidity::get_add_liquidity_amount_and_fee(&valuation, &custody_prices, lp_supply, token_id, amount_in)?
+        };
 
-        Ok((sflp_price_usd, flp_price))
+        msg!("LP amount out: {}, Fee: {}", quote.amount_out, quote.fee_amount);
+
+        Ok(quote)
     }
 
-    pub fn get_liquidation_price(
-        ctx: Context<GetLiquidationPrice>,
-    ) -> Result<OraclePrice> {
-        let position = &ctx.accounts.position;
+    pub fn get_remove_liquidity_amount_and_fee(
+        ctx: Context<GetLiquidityAmountAndFee>,
+        lp_amount_in: u64,
+        compounding: bool,
+    ) -> Result<LiquidityQuote> {
         let pool = &ctx.accounts.pool;
-        let market = &ctx.accounts.market;
-        let target_custody = &ctx.accounts.target_custody;
-        let collateral_custody = &ctx.accounts.collateral_custody;
-    
-        let liabilities_usd = math::checked_add(
-            math::checked_add(
-                pool.get_fee_amount(position.size_usd, target_custody.fees.close_position)?,
-                collateral_custody.get_lock_fee_usd(position, solana_program::sysvar::clock::Clock::get()?.unix_timestamp)?
-            )?,
-            math::checked_add(
-                position.unsettled_fees_usd,
-                math::checked_as_u64(math::checked_div(
-                    math::checked_mul(position.size_usd as u128, Perpetuals::BPS_POWER)?,
-                    target_custody.pricing.max_leverage as u128,
-                )?)?
-            )?,
+        let (custodies, custody_prices) = get_pool_custodies(
+            pool,
+            ctx.remaining_accounts,
+            solana_program::sysvar::clock::Clock::get()?.unix_timestamp,
         )?;
+        let markets = get_pool_markets(pool, ctx.remaining_accounts)?;
+        let valuation = PoolValuation::new(pool, &custodies, &markets)?;
+        let token_id = pool.get_custody_id(&ctx.accounts.custody.key())?;
+        let lp_supply = ctx.accounts.lp_token_mint.supply;
 
-        if position.collateral_usd >= liabilities_usd {
-            // Position is nominally solvent and shall be liqudaited in case of loss
-            let mut price_diff_loss = OraclePrice::new(
-                math::checked_as_u64(math::checked_div(
-                    math::checked_mul(
-                        math::checked_sub(position.collateral_usd, liabilities_usd)? as u128,
-                        math::checked_pow(10_u128, (position.size_decimals + 3) as usize)?,
-                    )?,
-                    position.size_amount as u128,
-                )?)?,
-                -(Perpetuals::RATE_DECIMALS as i32),
-            ).scale_to_exponent(position.entry_price.exponent)?;
-            if market.side == Side::Long {
-                // For Longs, loss implies price drop
-                price_diff_loss.price = position.entry_price.price.saturating_sub(price_diff_loss.price);
-            } else {
-                // For Shorts, loss implies price rise
-                price_diff_loss.price = position.entry_price.price.saturating_add(price_diff_loss.price);
-            }
-            Ok(price_diff_loss)
+        let quote = if compounding {
+            liquidity::get_remove_compounding_liquidity_amount_and_fee(&valuation, &custody_prices, lp_supply, token_id, lp_amount_in)?
         } else {
-            // Position is nominally insolvent and shall be liqudaited with profit to cover outstanding liabilities
-            let mut price_diff_profit = OraclePrice::new(
-                math::checked_as_u64(math::checked_div(
-                    math::checked_mul(
-                        math::checked_sub(liabilities_usd, position.collateral_usd)? as u128,
-                        math::checked_pow(10_u128, (position.size_decimals + 3) as usize)?,
-                    )?,
-                    position.size_amount as u128,
-                )?)?,
-                -(Perpetuals::RATE_DECIMALS as i32),
-            ).scale_to_exponent(position.entry_price.exponent)?;
-            if market.side == Side::Long {
-                // For Longs, profit implies price rise
-                price_diff_profit.price = position.entry_price.price.saturating_add(price_diff_profit.price);
-            } else {
-                // For Shorts, profit implies price drop
-                price_diff_profit.price = position.entry_price.price.saturating_sub(price_diff_profit.price);
-            }
-            Ok(price_diff_profit)
+            liquidity::get_remove_liquidity_amount_and_fee(&valuation, &custody_prices, lp_supply, token_id, lp_amount_in)?
+        };
+
+        msg!("Amount out: {}, Fee: {}", quote.amount_out, quote.fee_amount);
+
+        Ok(quote)
+    }
+
+    pub fn get_custody_price(
+        ctx: Context<GetCustodyPrice>,
+    ) -> Result<ResolvedPrice> {
+        let price = oracle::resolve_custody_price_from_accounts(
+            &ctx.accounts.custody,
+            &ctx.accounts.int_oracle_account,
+            &ctx.accounts.ext_oracle_account,
+            solana_program::sysvar::clock::Clock::get()?.unix_timestamp,
+        )?;
+
+        msg!("Min Price: {}, Max Price: {}, Backup: {}", price.min_price.price, price.max_price.price, price.is_backup);
+
+        Ok(price)
+    }
+}
+
+// Reads the price published in a Pyth PriceUpdateV2 account, validated against custody.oracle
+fn get_pyth_price(oracle_account: &AccountInfo, custody: &Custody, curtime: i64) -> Result<OraclePrice> {
+    let pyth_price = Account::<PriceUpdateV2>::try_from(oracle_account)?;
+    OraclePrice::get_pyth_price(&pyth_price, &custody.oracle, curtime, custody.is_stable)
+}
+
+// Returns the (min, max) price range of a Pyth PriceUpdateV2 account for the custody
+fn fetch_pyth_price(oracle_account: &AccountInfo, custody: &Custody, curtime: i64) -> Result<(OraclePrice, OraclePrice)> {
+    let pyth_price = Account::<PriceUpdateV2>::try_from(oracle_account)?;
+    let (min_price, max_price, _) = OraclePrice::fetch_from_pyth_oracle(
+        &pyth_price,
+        &custody.oracle,
+        curtime,
+        custody.is_stable,
+    )?;
+    Ok((min_price, max_price))
+}
+
+// Decodes the custody accounts and their Pyth oracles from remaining accounts
+fn get_pool_custodies(pool: &Pool, remaining_accounts: &[AccountInfo], curtime: i64) -> Result<(Vec<Custody>, Vec<OraclePrice>)> {
+    let mut custodies: Vec<Custody> = Vec::with_capacity(pool.custodies.len());
+    let mut custody_prices: Vec<OraclePrice> = Vec::with_capacity(pool.custodies.len());
+
+    for (idx, &custody) in pool.custodies.iter().enumerate() {
+        let oracle_idx = idx + pool.custodies.len();
+        if oracle_idx >= remaining_accounts.len() {
+            return Err(ProgramError::NotEnoughAccountKeys.into());
         }
+        require_keys_eq!(remaining_accounts[idx].key(), custody);
+        let custody = Account::<Custody>::try_from(&remaining_accounts[idx])?.into_inner();
+        require_keys_eq!(remaining_accounts[oracle_idx].key(), custody.oracle.ext_oracle_account);
+
+        custody_prices.push(get_pyth_price(&remaining_accounts[oracle_idx], &custody, curtime)?);
+        custodies.push(custody);
+    }
+    Ok((custodies, custody_prices))
+}
+
+// Decodes the market accounts following the custodies and their oracles in remaining accounts
+fn get_pool_markets(pool: &Pool, remaining_accounts: &[AccountInfo]) -> Result<Vec<Market>> {
+    let mut markets: Vec<Market> = Vec::with_capacity(pool.markets.len());
+    for (idx, &market) in pool.markets.iter().enumerate() {
+        let market_idx = (pool.custodies.len() * 2) + idx;
+        if market_idx >= remaining_accounts.len() {
+            return Err(ProgramError::NotEnoughAccountKeys.into());
+        }
+        require_keys_eq!(remaining_accounts[market_idx].key(), market);
+        markets.push(Account::<Market>::try_from(&remaining_accounts[market_idx])?.into_inner());
     }
+    Ok(markets)
 }
 
 #[derive(Accounts)]
@@ -363,18 +373,21 @@ pub struct GetRealtimePoolTokenPrices<'info> {
     //   pool.markets.len() market accounts (read-only, unsigned)
 }
 
+// Shared by the position views: liquidation price, PnL and health
 #[derive(Accounts)]
 pub struct GetLiquidationPrice<'info> {
     #[account(
         seeds = [b"perpetuals"],
-        bump = perpetuals.perpetuals_bump
+        bump = perpetuals.perpetuals_bump,
+        seeds::program = FLASH_PROGRAM,
     )]
     pub perpetuals: Box<Account<'info, Perpetuals>>,
 
     #[account(
         seeds = [b"pool",
                  pool.name.as_bytes()],
-        bump = pool.bump
+        bump = pool.bump,
+        seeds::program = FLASH_PROGRAM,
     )]
     pub pool: Box<Account<'info, Pool>>,
 
@@ -382,7 +395,8 @@ pub struct GetLiquidationPrice<'info> {
         seeds = [b"position",
                  position.owner.as_ref(),
                  market.key().as_ref()],
-        bump = position.bump
+        bump = position.bump,
+        seeds::program = FLASH_PROGRAM,
     )]
     pub position: Box<Account<'info, Position>>,
 
@@ -391,7 +405,8 @@ pub struct GetLiquidationPrice<'info> {
                  target_custody.key().as_ref(),
                  collateral_custody.key().as_ref(),
                  &[market.side as u8]],
-        bump = market.bump
+        bump = market.bump,
+        seeds::program = FLASH_PROGRAM,
     )]
     pub market: Box<Account<'info, Market>>,
 
@@ -399,7 +414,8 @@ pub struct GetLiquidationPrice<'info> {
         seeds = [b"custody",
                  pool.key().as_ref(),
                  target_custody.mint.key().as_ref()],
-        bump = target_custody.bump
+        bump = target_custody.bump,
+        seeds::program = FLASH_PROGRAM,
     )]
     pub target_custody: Box<Account<'info, Custody>>,
 
@@ -414,12 +430,132 @@ pub struct GetLiquidationPrice<'info> {
                  pool.key().as_ref(),
                  collateral_custody.mint.key().as_ref()],
         bump = collateral_custody.bump,
+        seeds::program = FLASH_PROGRAM,
     )]
     pub collateral_custody: Box<Account<'info, Custody>>,
 
     /// CHECK: oracle account for the collateral token
     #[account(
-        constraint = collateral_oracle_account.key() == collateral_custody.oracle.ext_oracle_account 
+        constraint = collateral_oracle_account.key() == collateral_custody.oracle.ext_oracle_account
     )]
     pub collateral_oracle_account: AccountInfo<'info>
 }
+
+#[derive(Accounts)]
+pub struct GetSwapAmountAndFees<'info> {
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 receiving_custody.mint.key().as_ref()],
+        bump = receiving_custody.bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub receiving_custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 dispensing_custody.mint.key().as_ref()],
+        bump = dispensing_custody.bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub dispensing_custody: Box<Account<'info, Custody>>,
+
+    // remaining accounts:
+    //   pool.custodies.len() custody accounts (read-only, unsigned)
+    //   pool.custodies.len() custody oracles (read-only, unsigned)
+}
+
+#[derive(Accounts)]
+pub struct GetLiquidityAmountAndFee<'info> {
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.key().as_ref()],
+        bump = custody.bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    #[account(
+        seeds = [b"lp_token_mint",
+                 pool.key().as_ref()],
+        bump = pool.lp_mint_bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub lp_token_mint: Box<Account<'info, Mint>>,
+
+    // remaining accounts:
+    //   pool.custodies.len() custody accounts (read-only, unsigned)
+    //   pool.custodies.len() custody oracles (read-only, unsigned)
+    //   pool.markets.len() market accounts (read-only, unsigned)
+}
+
+#[derive(Accounts)]
+pub struct GetCustodyPrice<'info> {
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+
+    #[account(
+        seeds = [b"pool",
+                 pool.name.as_bytes()],
+        bump = pool.bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        seeds = [b"custody",
+                 pool.key().as_ref(),
+                 custody.mint.key().as_ref()],
+        bump = custody.bump,
+        seeds::program = FLASH_PROGRAM,
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: custom oracle account of the custody
+    #[account(
+        constraint = int_oracle_account.key() == custody.oracle.int_oracle_account
+    )]
+    pub int_oracle_account: AccountInfo<'info>,
+
+    /// CHECK: Pyth oracle account of the custody
+    #[account(
+        constraint = ext_oracle_account.key() == custody.oracle.ext_oracle_account
+    )]
+    pub ext_oracle_account: AccountInfo<'info>
+}
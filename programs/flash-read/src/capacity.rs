@@ -0,0 +1,194 @@
+//! Remaining trading capacity of the custodies and markets of a pool.
+
+use {
+    crate::{math, states::*},
+    anchor_lang::prelude::*,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct CustodyCapacity {
+    pub utilization_bps: u64,
+    // Tokens that can still be locked before reaching pricing.max_utilization, nothing can
+    // be locked while it is zero
+    pub remaining_lockable_amount: u64,
+    pub remaining_lockable_usd: u64,
+    // Size of the positions targeting the custody, across all its markets
+    pub exposure_usd: u64,
+    // Size that can still be opened before reaching pricing.max_exposure_usd
+    pub remaining_exposure_usd: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct MarketCapacity {
+    // Size of the open positions of the market (collective_position.size_usd)
+    pub collective_size_usd: u64,
+    // Largest position a new trader can open right now, limited by the collateral custody
+    // lockable amount, the target custody exposure and pricing.max_position_locked_usd
+    pub max_position_size_usd: u64,
+}
+
+#[derive(Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct CapacityReport {
+    // In pool.custodies order
+    pub custodies: Vec<CustodyCapacity>,
+    // In pool.markets order
+    pub markets: Vec<MarketCapacity>,
+}
+
+// custodies and prices follow pool.custodies order, markets follow pool.markets order
+pub fn get_capacity_report(
+    pool: &Pool,
+    custodies: &[Custody],
+    markets: &[Market],
+    prices: &[OraclePrice],
+) -> Result<CapacityReport> {
+    if custodies.len() != pool.custodies.len()
+        || prices.len() != pool.custodies.len()
+        || markets.len() != pool.markets.len()
+    {
+        return Err(ProgramError::NotEnoughAccountKeys.into());
+    }
+
+    let mut exposures_usd: Vec<u64> = vec![0; custodies.len()];
+    for market in markets.iter() {
+        let target_custody_id = pool.get_custody_id(&market.target_custody)?;
+        exposures_usd[target_custody_id] = math::checked_add(
+            exposures_usd[target_custody_id],
+            market.collective_position.size_usd,
+        )?;
+    }
+
+    let mut custody_capacities: Vec<CustodyCapacity> = Vec::with_capacity(custodies.len());
+    for (idx, custody) in custodies.iter().enumerate() {
+        let utilization_bps = math::checked_as_u64(math::checked_div(
+            math::checked_mul(custody.get_utilization()? as u128, Perpetuals::BPS_POWER)?,
+            Perpetuals::RATE_POWER,
+        )?)?;
+
+        let max_lockable_amount = math::checked_as_u64(math::checked_div(
+            math::checked_mul(
+                custody.assets.owned as u128,
+                custody.pricing.max_utilization as u128,
+            )?,
+            Perpetuals::BPS_POWER,
+        )?)?;
+        let remaining_lockable_amount = max_lockable_amount.saturating_sub(custody.assets.locked);
+
+        custody_capacities.push(CustodyCapacity {
+            utilization_bps,
+            remaining_lockable_amount,
+            remaining_lockable_usd: prices[idx]
+                .get_asset_amount_usd(remaining_lockable_amount, custody.decimals)?,
+            exposure_usd: exposures_usd[idx],
+            remaining_exposure_usd: custody
+                .pricing
+                .max_exposure_usd
+                .saturating_sub(exposures_usd[idx]),
+        });
+    }
+
+    let mut market_capacities: Vec<MarketCapacity> = Vec::with_capacity(markets.len());
+    for market in markets.iter() {
+        let target_custody_id = pool.get_custody_id(&market.target_custody)?;
+        let collateral_custody_id = pool.get_custody_id(&market.collateral_custody)?;
+        let target_custody = &custodies[target_custody_id];
+
+        // Opening a position locks its size in the collateral custody
+        let max_position_size_usd = std::cmp::min(
+            std::cmp::min(
+                custody_capacities[collateral_custody_id].remaining_lockable_usd,
+                custody_capacities[target_custody_id].remaining_exposure_usd,
+            ),
+            target_custody.pricing.max_position_locked_usd,
+        );
+
+        market_capacities.push(MarketCapacity {
+            collective_size_usd: market.collective_position.size_usd,
+            max_position_size_usd,
+        });
+    }
+
+    Ok(CapacityReport {
+        custodies: custody_capacities,
+        markets: market_capacities,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_capacity_report() {
+        let sol_custody_key = Pubkey::new_unique();
+        let usdc_custody_key = Pubkey::new_unique();
+        let sol_custody = Custody {
+            decimals: 9,
+            pricing: PricingParams {
+                max_utilization: 8000,
+                max_position_locked_usd: 1_000_000_000_000,
+                max_exposure_usd: 3_000_000_000_000,
+                ..PricingParams::default()
+            },
+            assets: Assets {
+                owned: 10_000_000_000_000,
+                locked: 2_000_000_000_000,
+                ..Assets::default()
+            },
+            ..Custody::default()
+        };
+        // No utilization allowed
+        let usdc_custody = Custody {
+            decimals: 6,
+            assets: Assets {
+                owned: 1_000_000_000_000,
+                ..Assets::default()
+            },
+            ..Custody::default()
+        };
+        let market = |collateral_custody: Pubkey, side: Side| Market {
+            target_custody: sol_custody_key,
+            collateral_custody,
+            side,
+            collective_position: PositionStats {
+                size_usd: 1_000_000_000_000,
+                ..PositionStats::default()
+            },
+            ..Market::default()
+        };
+        let markets = [market(sol_custody_key, Side::Long), market(usdc_custody_key, Side::Short)];
+        let pool = Pool {
+            custodies: vec![sol_custody_key, usdc_custody_key],
+            markets: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            ..Pool::default()
+        };
+        let prices = [OraclePrice::new(100_000_000, -6), OraclePrice::new(1_000_000, -6)];
+
+        let report = get_capacity_report(&pool, &[sol_custody, usdc_custody], &markets, &prices).unwrap();
+
+        assert_eq!(
+            report.custodies[0],
+            CustodyCapacity {
+                utilization_bps: 2000,
+                remaining_lockable_amount: 6_000_000_000_000,
+                remaining_lockable_usd: 600_000_000_000,
+                exposure_usd: 2_000_000_000_000,
+                remaining_exposure_usd: 1_000_000_000_000,
+            }
+        );
+        assert_eq!(report.custodies[1].remaining_lockable_amount, 0);
+        assert_eq!(
+            report.markets,
+            vec![
+                MarketCapacity {
+                    collective_size_usd: 1_000_000_000_000,
+                    max_position_size_usd: 600_000_000_000,
+                },
+                MarketCapacity {
+                    collective_size_usd: 1_000_000_000_000,
+                    max_position_size_usd: 0,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Growth tracking of the compounding LP token.
+
+use {
+    crate::{error::CompError, math, states::*},
+    anchor_lang::prelude::*,
+};
+
+const YEAR_SECONDS: i64 = 365 * Perpetuals::DAY_SECONDS;
+
+// Fixed point numbers used to compound returns, with 18 implied decimals
+const FIXED_POWER: i128 = 1_000_000_000_000_000_000;
+const LN_2: i128 = 693_147_180_559_945_309;
+// e^34 - 1 in BPS_DECIMALS still fits an i64
+const MAX_EXPONENT: i128 = 34 * FIXED_POWER;
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct CompoundingObservation {
+    // pool.last_updated_timestamp, when lp_price and compounding_lp_price were last updated
+    pub price_time: i64,
+    // compounding_stats.last_compound_time, when the compounding factor last changed
+    pub compound_time: i64,
+    pub reward_snapshot: u128,
+    // Staked LP tokens backing one compounding token (LP_DECIMALS)
+    pub compounding_factor: u64,
+    pub lp_price: u64,
+    pub compounding_lp_price: u64,
+}
+
+impl CompoundingObservation {
+    pub fn from_pool(pool: &Pool) -> Result<Self> {
+        let stats = &pool.compounding_stats;
+        // Compounding tokens are minted 1:1 until the first compounding
+        let compounding_factor = if stats.active_amount == 0 || stats.total_supply == 0 {
+            Perpetuals::LP_POWER as u64
+        } else {
+            math::checked_decimal_div(
+                stats.active_amount,
+                -(Perpetuals::LP_DECIMALS as i32),
+                stats.total_supply,
+                -(Perpetuals::LP_DECIMALS as i32),
+                -(Perpetuals::LP_DECIMALS as i32),
+            )?
+        };
+        Ok(Self {
+            price_time: pool.last_updated_timestamp,
+            compound_time: stats.last_compound_time,
+            reward_snapshot: stats.reward_snapshot,
+            compounding_factor,
+            lp_price: pool.lp_price,
+            compounding_lp_price: pool.compounding_lp_price,
+        })
+    }
+}
+
+// Returns are signed and have implied BPS_DECIMALS
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct CompoundingGrowth {
+    // Time between the price updates, over which the returns are measured
+    pub elapsed_sec: i64,
+    // Time between the compoundings, over which the fees were reinvested
+    pub compound_elapsed_sec: i64,
+    // Rewards per staked LP token compounded over the period
+    pub reward_per_lp_compounded: u128,
+    // Growth of the compounding factor, i.e. the fees reinvested into staked LP tokens
+    pub fee_compounding_bps: i64,
+    // Growth of the staked LP token price
+    pub price_appreciation_bps: i64,
+    // Growth of the compounding LP token price, combining both sources
+    pub total_return_bps: i64,
+    // Total return compounded over a year
+    pub apy_bps: i64,
+    // Total return annualized without compounding
+    pub apr_bps: i64,
+}
+
+/// Accumulates successive pool snapshots to measure the compounding token growth.
+#[derive(Clone, Default, Debug)]
+pub struct CompoundingTracker {
+    pub observations: Vec<CompoundingObservation>,
+}
+
+impl CompoundingTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Records the pool state, ignoring snapshots with neither a newer price update nor a
+    // newer compounding than the last one recorded
+    pub fn ingest(&mut self, pool: &Pool) -> Result<bool> {
+        let observation = CompoundingObservation::from_pool(pool)?;
+        if let Some(last) = self.observations.last() {
+            if observation.price_time <= last.price_time && observation.compound_time <= last.compound_time {
+                return Ok(false);
+            }
+        }
+        self.observations.push(observation);
+        Ok(true)
+    }
+
+    // Returns the growth between the first and the last recorded snapshots
+    pub fn get_growth(&self) -> Result<Option<CompoundingGrowth>> {
+        match (self.observations.first(), self.observations.last()) {
+            (Some(first), Some(last)) if last.price_time > first.price_time => {
+                Ok(Some(get_compounding_growth(first, last)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Returns the growth between each pair of consecutive snapshots
+    pub fn get_period_growths(&self) -> Result<Vec<CompoundingGrowth>> {
+        self.observations
+            .windows(2)
+            .map(|pair| get_compounding_growth(&pair[0], &pair[1]))
+            .collect()
+    }
+}
+
+pub fn get_compounding_growth(
+    start: &CompoundingObservation,
+    end: &CompoundingObservation,
+) -> Result<CompoundingGrowth> {
+    let elapsed_sec = math::checked_sub(end.price_time, start.price_time)?;
+    let total_return_bps = get_return_bps(start.compounding_lp_price, end.compounding_lp_price)?;
+    let (apy_bps, apr_bps) = if elapsed_sec > 0 {
+        (
+            get_apy_bps(start.compounding_lp_price, end.compounding_lp_price, elapsed_sec)?,
+            math::checked_div(
+                math::checked_mul(total_return_bps, YEAR_SECONDS)?,
+                elapsed_sec,
+            )?,
+        )
+    } else {
+        (0, 0)
+    };
+
+    Ok(CompoundingGrowth {
+        elapsed_sec,
+        compound_elapsed_sec: math::checked_sub(end.compound_time, start.compound_time)?,
+        reward_per_lp_compounded: end.reward_snapshot.saturating_sub(start.reward_snapshot),
+        fee_compounding_bps: get_return_bps(start.compounding_factor, end.compounding_factor)?,
+        price_appreciation_bps: get_return_bps(start.lp_price, end.lp_price)?,
+        total_return_bps,
+        apy_bps,
+        apr_bps,
+    })
+}
+
+fn get_return_bps(start: u64, end: u64) -> Result<i64> {
+    if start == 0 {
+        return Ok(0);
+    }
+    let change = math::checked_sub(end as i128, start as i128)?;
+    let return_bps = math::checked_div(
+        math::checked_mul(change, Perpetuals::BPS_POWER as i128)?,
+        start as i128,
+    )?;
+    i64::try_from(return_bps).map_err(|_| CompError::MathOverflow.into())
+}
+
+// Compounds the growth from start to end over elapsed_sec to a yearly return
+fn get_apy_bps(start: u64, end: u64, elapsed_sec: i64) -> Result<i64> {
+    if start == 0 {
+        return Ok(0);
+    }
+    let growth = math::checked_div(
+        math::checked_mul(end as i128, FIXED_POWER)?,
+        start as i128,
+    )?;
+    if growth == 0 {
+        return Ok(-(Perpetuals::BPS_POWER as i64));
+    }
+
+    // growth ^ (year / elapsed) = e ^ (ln(growth) * year / elapsed)
+    let exponent = math::checked_div(
+        math::checked_mul(get_ln(growth)?, YEAR_SECONDS as i128)?,
+        elapsed_sec as i128,
+    )?;
+    if exponent > MAX_EXPONENT {
+        return err!(CompError::MathOverflow);
+    }
+    let apy = math::checked_mul(
+        math::checked_sub(get_exp(exponent)?, FIXED_POWER)?,
+        Perpetuals::BPS_POWER as i128,
+    )?;
+
+    // Rounded half away from zero
+    let half = if apy < 0 { -FIXED_POWER / 2 } else { FIXED_POWER / 2 };
+    let apy_bps = math::checked_div(math::checked_add(apy, half)?, FIXED_POWER)?;
+    i64::try_from(apy_bps).map_err(|_| CompError::MathOverflow.into())
+}
+
+// Natural logarithm of a positive fixed point number
+fn get_ln(x: i128) -> Result<i128> {
+    // ln(x) = k * ln(2) + ln(m), with m in [1, 2)
+    let mut m = x;
+    let mut k: i128 = 0;
+    while m >= 2 * FIXED_POWER {
+        m /= 2;
+        k += 1;
+    }
+    while m < FIXED_POWER {
+        m = math::checked_mul(m, 2)?;
+        k -= 1;
+    }
+
+    // ln(m) = 2 * atanh(z) = 2 * (z + z^3 / 3 + z^5 / 5 + ...), with z = (m - 1) / (m + 1)
+    let z = math::checked_div(
+        math::checked_mul(m - FIXED_POWER, FIXED_POWER)?,
+        m + FIXED_POWER,
+    )?;
+    let z_squared = math::checked_div(math::checked_mul(z, z)?, FIXED_POWER)?;
+    let mut term = z;
+    let mut sum: i128 = 0;
+    let mut n: i128 = 1;
+    while term > 0 {
+        sum = math::checked_add(sum, term / n)?;
+        term = math::checked_div(math::checked_mul(term, z_squared)?, FIXED_POWER)?;
+        n += 2;
+    }
+
+    math::checked_add(math::checked_mul(k, LN_2)?, math::checked_mul(sum, 2)?)
+}
+
+// Exponential of a fixed point number, up to MAX_EXPONENT
+fn get_exp(x: i128) -> Result<i128> {
+    // e^x = 2^k * e^r, with r in [0, ln(2))
+    let mut k = x / LN_2;
+    let mut r = x % LN_2;
+    if r < 0 {
+        r += LN_2;
+        k -= 1;
+    }
+
+    // e^r = 1 + r + r^2 / 2! + r^3 / 3! + ...
+    let mut term = FIXED_POWER;
+    let mut sum = FIXED_POWER;
+    let mut n: i128 = 1;
+    while term > 0 {
+        term = math::checked_div(math::checked_div(math::checked_mul(term, r)?, FIXED_POWER)?, n)?;
+        sum = math::checked_add(sum, term)?;
+        n += 1;
+    }
+
+    if k >= 0 {
+        math::checked_mul(sum, math::checked_pow(2, k as usize)?)
+    } else if k > -127 {
+        math::checked_div(sum, math::checked_pow(2, (-k) as usize)?)
+    } else {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_pool(price_time: i64, compound_time: i64, compounding_lp_price: u64) -> Pool {
+        Pool {
+            last_updated_timestamp: price_time,
+            lp_price: 1_000_000,
+            compounding_lp_price,
+            compounding_stats: CompoundingStats {
+                active_amount: 1_100_000,
+                total_supply: 1_000_000,
+                last_compound_time: compound_time,
+                ..CompoundingStats::default()
+            },
+            ..Pool::default()
+        }
+    }
+
+    #[test]
+    fn test_get_apy_bps() {
+        // (start, end, elapsed_sec, expected apy_bps)
+        let cases = [
+            (1_000_000, 1_100_000, YEAR_SECONDS, 1000),
+            (1_000_000, 1_000_000, YEAR_SECONDS, 0),
+            (1_000_000, 1_050_000, YEAR_SECONDS / 2, 1025),
+            (1_000_000, 900_000, YEAR_SECONDS, -1000),
+            (1_000_000, 810_000, YEAR_SECONDS * 2, -1000),
+            (0, 1_000_000, YEAR_SECONDS, 0),
+            (1_000_000, 1_000_100, Perpetuals::DAY_SECONDS, 372),
+            (1_000_000, 2_000_000, YEAR_SECONDS * 10, 718),
+            (1_000_000, 0, YEAR_SECONDS, -10_000),
+            (1_000_000, 500_000, Perpetuals::DAY_SECONDS, -10_000),
+        ];
+        for (start, end, elapsed_sec, expected) in cases {
+            assert_eq!(get_apy_bps(start, end, elapsed_sec).unwrap(), expected);
+        }
+
+        // Returns too large for an i64
+        assert_eq!(
+            get_apy_bps(1_000_000, 2_000_000, Perpetuals::DAY_SECONDS).unwrap_err(),
+            CompError::MathOverflow.into()
+        );
+    }
+
+    #[test]
+    fn test_ingest_price_updates_without_compounding() {
+        let mut tracker = CompoundingTracker::new();
+        assert!(tracker.ingest(&get_pool(100, 50, 1_000_000)).unwrap());
+        assert!(tracker.ingest(&get_pool(100 + YEAR_SECONDS / 2, 50, 1_050_000)).unwrap());
+        assert!(!tracker.ingest(&get_pool(100 + YEAR_SECONDS / 2, 50, 1_050_000)).unwrap());
+        assert!(tracker.ingest(&get_pool(100 + YEAR_SECONDS, 80, 1_102_500)).unwrap());
+
+        let growth = tracker.get_growth().unwrap().unwrap();
+        assert_eq!(growth.elapsed_sec, YEAR_SECONDS);
+        assert_eq!(growth.compound_elapsed_sec, 30);
+        assert_eq!(growth.total_return_bps, 1025);
+        assert_eq!(growth.apy_bps, 1025);
+        assert_eq!(growth.apr_bps, 1025);
+
+        let period_growths = tracker.get_period_growths().unwrap();
+        assert_eq!(period_growths.len(), 2);
+        assert_eq!(period_growths[0].total_return_bps, 500);
+        assert_eq!(period_growths[0].apy_bps, 1025);
+        assert_eq!(period_growths[0].apr_bps, 1000);
+    }
+
+    #[test]
+    fn test_fresh_pool_compounding_factor() {
+        let mut pool = get_pool(0, 0, 1_000_000);
+        pool.compounding_stats.total_supply = 0;
+        let observation = CompoundingObservation::from_pool(&pool).unwrap();
+        assert_eq!(observation.compounding_factor, Perpetuals::LP_POWER as u64);
+    }
+}
@@ -0,0 +1,188 @@
+//! Trading fee discounts, referral rebates and staking boosts of tier levels.
+
+use {
+    crate::{error::CompError, math, states::*},
+    anchor_lang::prelude::*,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct DiscountedFee {
+    // Fee charged to the trader after the discount
+    pub fee_usd: u64,
+    pub discount_usd: u64,
+    // Share of the charged fee paid to the referrer
+    pub rebate_usd: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct TradingFees {
+    pub open_position: DiscountedFee,
+    pub close_position: DiscountedFee,
+    // Boost of the LP staking fee share for the trader's level
+    pub staking_fee_boost_bps: u64,
+}
+
+// Levels index the tier arrays of Perpetuals and Pool
+fn get_tier<T: Copy>(tiers: &[T], level: usize) -> Result<T> {
+    tiers
+        .get(level)
+        .copied()
+        .ok_or_else(|| CompError::InvalidTierLevel.into())
+}
+
+/// Applies the trader's tier discount to `fee_usd` and computes the referrer's rebate.
+///
+/// Referred traders get the larger of their tier discount and `referral_discount`. The rebate
+/// is taken from the discounted fee at the referrer's level and capped by `rebate_limit_usd`,
+/// which is expressed in whole USD. Discounts and rebates are only granted while enabled by both the
+/// protocol and the pool permissions.
+pub fn get_discounted_fee(
+    perpetuals: &Perpetuals,
+    pool: &Pool,
+    fee_usd: u64,
+    level: usize,
+    referrer_level: Option<usize>,
+) -> Result<DiscountedFee> {
+    let mut discount_rate = 0;
+    if perpetuals.permissions.allow_fee_discounts && pool.permissions.allow_fee_discounts {
+        discount_rate = get_tier(&perpetuals.trading_discount, level)?;
+        if referrer_level.is_some() {
+            discount_rate = std::cmp::max(discount_rate, perpetuals.referral_discount);
+        }
+    }
+    let discount_rate = std::cmp::min(discount_rate, Perpetuals::RATE_POWER as u64);
+    let discount_usd = math::checked_as_u64(math::checked_div(
+        math::checked_mul(fee_usd as u128, discount_rate as u128)?,
+        Perpetuals::RATE_POWER,
+    )?)?;
+    let fee_usd = math::checked_sub(fee_usd, discount_usd)?;
+
+    let rebate_usd = match referrer_level {
+        Some(referrer_level)
+            if perpetuals.permissions.allow_referral_rebates
+                && pool.permissions.allow_referral_rebates =>
+        {
+            let rebate_usd = math::checked_as_u64(math::checked_div(
+                math::checked_mul(
+                    fee_usd as u128,
+                    get_tier(&perpetuals.referral_rebate, referrer_level)? as u128,
+                )?,
+                Perpetuals::RATE_POWER,
+            )?)?;
+            let rebate_limit_usd = math::checked_mul(
+                perpetuals.rebate_limit_usd as u64,
+                math::checked_pow(10u64, Perpetuals::USD_DECIMALS as usize)?,
+            )?;
+            std::cmp::min(std::cmp::min(rebate_usd, rebate_limit_usd), fee_usd)
+        }
+        _ => 0,
+    };
+
+    Ok(DiscountedFee {
+        fee_usd,
+        discount_usd,
+        rebate_usd,
+    })
+}
+
+/// Effective open and close fees of a `size_usd` position on `custody` for a trader of the
+/// given level, optionally referred by a referrer of `referrer_level`.
+pub fn get_trading_fees(
+    perpetuals: &Perpetuals,
+    pool: &Pool,
+    custody: &Custody,
+    size_usd: u64,
+    level: usize,
+    referrer_level: Option<usize>,
+) -> Result<TradingFees> {
+    let open_fee_usd = pool.get_fee_amount(custody.fees.open_position, size_usd)?;
+    let close_fee_usd = pool.get_fee_amount(custody.fees.close_position, size_usd)?;
+
+    Ok(TradingFees {
+        open_position: get_discounted_fee(perpetuals, pool, open_fee_usd, level, referrer_level)?,
+        close_position: get_discounted_fee(perpetuals, pool, close_fee_usd, level, referrer_level)?,
+        staking_fee_boost_bps: get_tier(&pool.staking_fee_boost_bps, level)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_fixtures() -> (Perpetuals, Pool) {
+        let permissions = Permissions {
+            allow_fee_discounts: true,
+            allow_referral_rebates: true,
+            ..Permissions::default()
+        };
+        let perpetuals = Perpetuals {
+            permissions,
+            // 0%, 5%, 10%, 15%, 20%, 25%
+            trading_discount: [0, 50_000_000, 100_000_000, 150_000_000, 200_000_000, 250_000_000],
+            // 10% to 35%
+            referral_rebate: [100_000_000, 150_000_000, 200_000_000, 250_000_000, 300_000_000, 350_000_000],
+            referral_discount: 75_000_000,
+            rebate_limit_usd: 50,
+            ..Perpetuals::default()
+        };
+        let pool = Pool {
+            permissions,
+            ..Pool::default()
+        };
+        (perpetuals, pool)
+    }
+
+    #[test]
+    fn test_get_discounted_fee() {
+        let (perpetuals, pool) = get_fixtures();
+        // (fee_usd, level, referrer_level, expected)
+        let cases = [
+            (100_000_000, 0, None, (100_000_000, 0, 0)),
+            (100_000_000, 2, None, (90_000_000, 10_000_000, 0)),
+            // Referral discount beats the tier discount
+            (100_000_000, 1, Some(0), (92_500_000, 7_500_000, 9_250_000)),
+            // Tier discount beats the referral discount
+            (100_000_000, 5, Some(5), (75_000_000, 25_000_000, 26_250_000)),
+            // Rebate capped at 50 USD
+            (1_000_000_000, 0, Some(5), (925_000_000, 75_000_000, 50_000_000)),
+        ];
+        for (fee_usd, level, referrer_level, (expected_fee_usd, discount_usd, rebate_usd)) in cases {
+            assert_eq!(
+                get_discounted_fee(&perpetuals, &pool, fee_usd, level, referrer_level).unwrap(),
+                DiscountedFee {
+                    fee_usd: expected_fee_usd,
+                    discount_usd,
+                    rebate_usd,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_discounted_fee_disabled() {
+        let (mut perpetuals, mut pool) = get_fixtures();
+        pool.permissions.allow_fee_discounts = false;
+        perpetuals.permissions.allow_referral_rebates = false;
+        assert_eq!(
+            get_discounted_fee(&perpetuals, &pool, 100_000_000, 5, Some(5)).unwrap(),
+            DiscountedFee {
+                fee_usd: 100_000_000,
+                discount_usd: 0,
+                rebate_usd: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_discounted_fee_invalid_level() {
+        let (perpetuals, pool) = get_fixtures();
+        assert_eq!(
+            get_discounted_fee(&perpetuals, &pool, 100_000_000, 6, None).unwrap_err(),
+            CompError::InvalidTierLevel.into()
+        );
+        assert_eq!(
+            get_discounted_fee(&perpetuals, &pool, 100_000_000, 0, Some(6)).unwrap_err(),
+            CompError::InvalidTierLevel.into()
+        );
+    }
+}
@@ -12,4 +12,34 @@ pub enum CompError {
     InvalidOraclePrice,
     #[msg("Custody is not supported")]
     UnsupportedCustody,
+    #[msg("Instruction is not allowed at this time")]
+    InstructionNotAllowed,
+    #[msg("Swap between the same custody")]
+    SameCustodySwap,
+    #[msg("Not enough liquidity in the custody")]
+    InsufficientLiquidity,
+    #[msg("Invalid token ratios")]
+    InvalidTokenRatios,
+    #[msg("Invalid take profit price")]
+    InvalidTakeProfitPrice,
+    #[msg("Invalid stop loss price")]
+    InvalidStopLossPrice,
+    #[msg("Not enough collateral")]
+    InsufficientCollateral,
+    #[msg("Leverage is below the minimum")]
+    MinLeverage,
+    #[msg("Leverage is above the maximum")]
+    MaxLeverage,
+    #[msg("Position exceeds the locked amount limit")]
+    MaxPositionLocked,
+    #[msg("Invalid position size change")]
+    InvalidSizeDelta,
+    #[msg("Invalid tier level")]
+    InvalidTierLevel,
+    #[msg("Perpetuals account is missing")]
+    MissingPerpetuals,
+    #[msg("More than one Perpetuals account")]
+    MultiplePerpetuals,
+    #[msg("Account is missing")]
+    MissingAccount,
 }
@@ -0,0 +1,206 @@
+//! Fees that depend on the token ratios of the pool.
+
+use {
+    crate::{error::CompError, math, states::*},
+    anchor_lang::prelude::*,
+};
+
+// Share of the pool AUM held by a custody, with implied BPS_DECIMALS
+pub fn get_token_ratio(custody_aum_usd: u64, pool_aum_usd: u64) -> Result<u64> {
+    if pool_aum_usd == 0 {
+        return Ok(0);
+    }
+    math::checked_as_u64(math::checked_div(
+        math::checked_mul(custody_aum_usd as u128, Perpetuals::BPS_POWER)?,
+        pool_aum_usd as u128,
+    )?)
+}
+
+// Returns the fee rate (RATE_DECIMALS) charged for moving the custody to new_ratio.
+//
+// In FeesMode::Fixed the target fee is charged regardless of the ratio.
+// In FeesMode::Linear operations that increase the custody share (is_increase) cost
+// min_fee when the custody ends up at ratios.min, target_fee at ratios.target and
+// max_fee at ratios.max, interpolating linearly in between; decreasing operations use
+// the mirrored curve. Ratios beyond min or max are charged the fee of that boundary.
+pub fn get_ratio_fee(
+    mode: FeesMode,
+    fees: &RatioFees,
+    ratios: &TokenRatios,
+    new_ratio: u64,
+    is_increase: bool,
+) -> Result<u64> {
+    if mode == FeesMode::Fixed {
+        return Ok(fees.target_fee);
+    }
+    require!(
+        ratios.min <= ratios.target && ratios.target <= ratios.max,
+        CompError::InvalidTokenRatios
+    );
+
+    let (cheap_end, expensive_end) = if is_increase {
+        (ratios.min, ratios.max)
+    } else {
+        (ratios.max, ratios.min)
+    };
+
+    let is_cheap_side = if is_increase {
+        new_ratio <= ratios.target
+    } else {
+        new_ratio >= ratios.target
+    };
+    if is_cheap_side {
+        interpolate(fees.min_fee, fees.target_fee, cheap_end, ratios.target, new_ratio)
+    } else {
+        interpolate(fees.target_fee, fees.max_fee, ratios.target, expensive_end, new_ratio)
+    }
+}
+
+// Linear interpolation of the fee between (from_ratio, from_fee) and (to_ratio, to_fee)
+fn interpolate(from_fee: u64, to_fee: u64, from_ratio: u64, to_ratio: u64, ratio: u64) -> Result<u64> {
+    let range = from_ratio.abs_diff(to_ratio);
+    if range == 0 {
+        return Ok(to_fee);
+    }
+    // Ratios outside of the segment are clamped to its ends
+    let progress = if from_ratio <= to_ratio {
+        ratio.clamp(from_ratio, to_ratio) - from_ratio
+    } else {
+        from_ratio - ratio.clamp(to_ratio, from_ratio)
+    };
+
+    if to_fee >= from_fee {
+        math::checked_add(
+            from_fee,
+            math::checked_as_u64(math::checked_div(
+                math::checked_mul((to_fee - from_fee) as u128, progress as u128)?,
+                range as u128,
+            )?)?,
+        )
+    } else {
+        math::checked_sub(
+            from_fee,
+            math::checked_as_u64(math::checked_div(
+                math::checked_mul((from_fee - to_fee) as u128, progress as u128)?,
+                range as u128,
+            )?)?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEES: RatioFees = RatioFees {
+        min_fee: 100,
+        target_fee: 200,
+        max_fee: 1000,
+    };
+
+    fn get_ratios(min: u64, target: u64, max: u64) -> TokenRatios {
+        TokenRatios { target, min, max }
+    }
+
+    #[test]
+    fn test_fixed_fee() {
+        for (new_ratio, is_increase) in [(0, true), (5000, false), (10000, true)] {
+            assert_eq!(
+                get_ratio_fee(FeesMode::Fixed, &FEES, &get_ratios(4000, 5000, 7000), new_ratio, is_increase).unwrap(),
+                FEES.target_fee
+            );
+        }
+        // Ratios aren't used in fixed mode
+        assert_eq!(
+            get_ratio_fee(FeesMode::Fixed, &FEES, &get_ratios(7000, 5000, 4000), 5000, true).unwrap(),
+            FEES.target_fee
+        );
+    }
+
+    #[test]
+    fn test_linear_fee() {
+        let ratios = get_ratios(4000, 5000, 7000);
+        // (new_ratio, is_increase, expected fee)
+        let cases = [
+            // Increases: min_fee at min, target_fee at target, max_fee at max
+            (0, true, 100),
+            (3999, true, 100),
+            (4000, true, 100),
+            (4100, true, 110),
+            (4500, true, 150),
+            (4900, true, 190),
+            (5000, true, 200),
+            (5100, true, 240),
+            (6000, true, 600),
+            (6900, true, 960),
+            (7000, true, 1000),
+            (7001, true, 1000),
+            (10000, true, 1000),
+            // Decreases: min_fee at max, target_fee at target, max_fee at min
+            (10000, false, 100),
+            (7001, false, 100),
+            (7000, false, 100),
+            (6900, false, 105),
+            (6000, false, 150),
+            (5100, false, 195),
+            (5000, false, 200),
+            (4900, false, 280),
+            (4500, false, 600),
+            (4100, false, 920),
+            (4000, false, 1000),
+            (3999, false, 1000),
+            (0, false, 1000),
+        ];
+        for (new_ratio, is_increase, expected) in cases {
+            assert_eq!(
+                get_ratio_fee(FeesMode::Linear, &FEES, &ratios, new_ratio, is_increase).unwrap(),
+                expected,
+                "new_ratio: {}, is_increase: {}",
+                new_ratio,
+                is_increase
+            );
+        }
+    }
+
+    #[test]
+    fn test_linear_fee_degenerate_ranges() {
+        // (ratios, new_ratio, is_increase, expected fee)
+        let cases = [
+            // min == target, the cheap segment collapses to target_fee
+            (get_ratios(5000, 5000, 7000), 4000, true, 200),
+            (get_ratios(5000, 5000, 7000), 5000, true, 200),
+            (get_ratios(5000, 5000, 7000), 6000, true, 600),
+            (get_ratios(5000, 5000, 7000), 4000, false, 1000),
+            // target == max, the expensive increase segment collapses to max_fee
+            (get_ratios(4000, 5000, 5000), 6000, true, 1000),
+            (get_ratios(4000, 5000, 5000), 4500, true, 150),
+            (get_ratios(4000, 5000, 5000), 6000, false, 200),
+            (get_ratios(4000, 5000, 5000), 4500, false, 600),
+            // min == target == max
+            (get_ratios(5000, 5000, 5000), 4000, true, 200),
+            (get_ratios(5000, 5000, 5000), 6000, true, 1000),
+            (get_ratios(5000, 5000, 5000), 6000, false, 200),
+            (get_ratios(5000, 5000, 5000), 4000, false, 1000),
+        ];
+        for (ratios, new_ratio, is_increase, expected) in cases {
+            assert_eq!(
+                get_ratio_fee(FeesMode::Linear, &FEES, &ratios, new_ratio, is_increase).unwrap(),
+                expected,
+                "ratios: {:?}, new_ratio: {}, is_increase: {}",
+                ratios,
+                new_ratio,
+                is_increase
+            );
+        }
+    }
+
+    #[test]
+    fn test_linear_fee_invalid_ratios() {
+        for ratios in [get_ratios(6000, 5000, 7000), get_ratios(4000, 5000, 4500)] {
+            assert_eq!(
+                get_ratio_fee(FeesMode::Linear, &FEES, &ratios, 5000, true).unwrap_err(),
+                CompError::InvalidTokenRatios.into()
+            );
+        }
+    }
+}
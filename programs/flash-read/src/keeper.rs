@@ -0,0 +1,357 @@
+//! Liquidation candidates search for keepers.
+
+use {
+    crate::{
+        error::CompError,
+        position::{PositionContext, PositionHealth},
+        snapshot::AccountSnapshot,
+        states::*,
+        valuation::CustodyPrice,
+    },
+    anchor_lang::prelude::*,
+    std::{cmp::Reverse, collections::HashMap},
+};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LiquidationReason {
+    // Leverage is above pricing.max_leverage of the target custody
+    MaxLeverageExceeded,
+    // Collateral doesn't cover the losses and fees anymore
+    Bankrupt,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LiquidationCandidate {
+    pub position: Pubkey,
+    pub owner: Pubkey,
+    pub market: Pubkey,
+    pub health: PositionHealth,
+    // Equity missing to get back to the maintenance margin
+    pub shortfall_usd: u64,
+    pub reason: LiquidationReason,
+}
+
+#[derive(Debug, Default)]
+pub struct LiquidationScan {
+    // Liquidatable positions, largest shortfall first
+    pub candidates: Vec<LiquidationCandidate>,
+    // Positions that could not be evaluated
+    pub skipped: Vec<(Pubkey, Error)>,
+}
+
+/// Returns the positions of `snapshot` that can be liquidated.
+///
+/// `prices` are keyed by custody address. The snapshot must hold exactly one Perpetuals
+/// account, otherwise an error is returned; if it disables liquidations the scan is empty.
+/// Positions are ignored when liquidations are disabled for their pool or either custody of
+/// their market. Positions whose market, pool, custodies or prices are missing, or whose
+/// health can't be computed, are reported in `skipped` instead of failing the scan.
+pub fn find_liquidation_candidates(
+    snapshot: &AccountSnapshot,
+    prices: &HashMap<Pubkey, CustodyPrice>,
+    curtime: i64,
+) -> Result<LiquidationScan> {
+    let mut perpetuals = snapshot.perpetuals.values();
+    let perpetuals = match (perpetuals.next(), perpetuals.next()) {
+        (Some(perpetuals), None) => perpetuals,
+        (None, _) => return err!(CompError::MissingPerpetuals),
+        (Some(_), Some(_)) => return err!(CompError::MultiplePerpetuals),
+    };
+    let mut scan = LiquidationScan::default();
+    if !perpetuals.permissions.allow_liquidation {
+        return Ok(scan);
+    }
+
+    for (key, position) in snapshot.positions.iter() {
+        match get_liquidatable_health(snapshot, prices, position, curtime) {
+            Ok(Some(health)) => scan.candidates.push(LiquidationCandidate {
+                position: *key,
+                owner: position.owner,
+                market: position.market,
+                health,
+                shortfall_usd: health.maintenance_margin_usd.saturating_sub(health.equity_usd),
+                reason: if health.equity_usd == 0 {
+                    LiquidationReason::Bankrupt
+                } else {
+                    LiquidationReason::MaxLeverageExceeded
+                },
+            }),
+            Ok(None) => {}
+            Err(err) => scan.skipped.push((*key, err)),
+        }
+    }
+
+    scan.candidates
+        .sort_by_key(|candidate| (Reverse(candidate.shortfall_usd), candidate.position));
+    scan.skipped.sort_by_key(|(key, _)| *key);
+    Ok(scan)
+}
+
+// Returns the health of the position if it can be liquidated
+fn get_liquidatable_health(
+    snapshot: &AccountSnapshot,
+    prices: &HashMap<Pubkey, CustodyPrice>,
+    position: &Position,
+    curtime: i64,
+) -> Result<Option<PositionHealth>> {
+    if position.size_amount == 0 {
+        return Ok(None);
+    }
+    let market = snapshot
+        .markets
+        .get(&position.market)
+        .ok_or(CompError::MissingAccount)?;
+    let (pool, target_custody, collateral_custody) = match (
+        snapshot.pools.get(&market.pool),
+        snapshot.custodies.get(&market.target_custody),
+        snapshot.custodies.get(&market.collateral_custody),
+    ) {
+        (Some(pool), Some(target_custody), Some(collateral_custody)) => {
+            (pool, target_custody, collateral_custody)
+        }
+        _ => return err!(CompError::MissingAccount),
+    };
+    if !(pool.permissions.allow_liquidation
+        && target_custody.permissions.allow_liquidation
+        && collateral_custody.permissions.allow_liquidation)
+    {
+        return Ok(None);
+    }
+    let (target_price, collateral_price) = match (
+        prices.get(&market.target_custody),
+        prices.get(&market.collateral_custody),
+    ) {
+        (Some(target_price), Some(collateral_price)) => (target_price, collateral_price),
+        _ => return err!(CompError::InvalidOraclePrice),
+    };
+
+    let context = PositionContext {
+        pool,
+        market,
+        target_custody,
+        collateral_custody,
+    };
+    let exit_price = context.get_exit_price(position, &target_price.min_price, &target_price.max_price)?;
+    let health = context.get_position_health(position, &exit_price, &collateral_price.price, curtime)?;
+    Ok(if health.is_liquidatable() { Some(health) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_permissions() -> Permissions {
+        Permissions {
+            allow_liquidation: true,
+            ..Permissions::default()
+        }
+    }
+
+    struct Fixture {
+        snapshot: AccountSnapshot,
+        prices: HashMap<Pubkey, CustodyPrice>,
+        pool: Pubkey,
+        target_custody: Pubkey,
+        market: Pubkey,
+    }
+
+    impl Fixture {
+        // SOL long market at 90 USD, liquidatable above 100x leverage
+        fn new() -> Self {
+            let (pool, target_custody, collateral_custody, market) = (
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+            );
+            let mut snapshot = AccountSnapshot::default();
+            snapshot.perpetuals.insert(
+                Pubkey::new_unique(),
+                Perpetuals {
+                    permissions: get_permissions(),
+                    ..Perpetuals::default()
+                },
+            );
+            snapshot.pools.insert(
+                pool,
+                Pool {
+                    permissions: get_permissions(),
+                    ..Pool::default()
+                },
+            );
+            for (custody, decimals) in [(target_custody, 9), (collateral_custody, 6)] {
+                snapshot.custodies.insert(
+                    custody,
+                    Custody {
+                        decimals,
+                        permissions: get_permissions(),
+                        pricing: PricingParams {
+                            max_leverage: 1_000_000,
+                            ..PricingParams::default()
+                        },
+                        ..Custody::default()
+                    },
+                );
+            }
+            snapshot.markets.insert(
+                market,
+                Market {
+                    pool,
+                    target_custody,
+                    collateral_custody,
+                    side: Side::Long,
+                    ..Market::default()
+                },
+            );
+
+            let price = |price: u64| {
+                let price = OraclePrice::new(price, -6);
+                CustodyPrice {
+                    price,
+                    min_price: price,
+                    max_price: price,
+                }
+            };
+            let prices = HashMap::from([(target_custody, price(90_000_000)), (collateral_custody, price(1_000_000))]);
+
+            Self {
+                snapshot,
+                prices,
+                pool,
+                target_custody,
+                market,
+            }
+        }
+
+        // Adds a 5 SOL long entered at 100 USD: 50 USD in loss, with 5 USD of maintenance margin
+        fn add_position(&mut self, market: Pubkey, collateral_usd: u64) -> Pubkey {
+            let key = Pubkey::new_unique();
+            self.snapshot.positions.insert(
+                key,
+                Position {
+                    owner: Pubkey::new_unique(),
+                    market,
+                    entry_price: OraclePrice::new(100_000_000, -6),
+                    size_amount: 5_000_000_000,
+                    size_usd: 500_000_000,
+                    size_decimals: 9,
+                    collateral_usd,
+                    collateral_decimals: 6,
+                    ..Position::default()
+                },
+            );
+            key
+        }
+
+        // Adds a copy of the market with one of its accounts replaced by an unknown one
+        fn add_market(&mut self, update: impl FnOnce(&mut Market)) -> Pubkey {
+            let key = Pubkey::new_unique();
+            let mut market = self.snapshot.markets[&self.market].clone();
+            update(&mut market);
+            self.snapshot.markets.insert(key, market);
+            key
+        }
+
+        fn scan(&self) -> LiquidationScan {
+            find_liquidation_candidates(&self.snapshot, &self.prices, 0).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_find_liquidation_candidates() {
+        let mut fixture = Fixture::new();
+        let market = fixture.market;
+        fixture.add_position(market, 100_000_000);
+        fixture.add_position(market, 55_000_000);
+        let max_leverage_small = fixture.add_position(market, 54_000_000);
+        let max_leverage_large = fixture.add_position(market, 52_000_000);
+        let bankrupt = fixture.add_position(market, 40_000_000);
+
+        let scan = fixture.scan();
+        assert!(scan.skipped.is_empty());
+        // Largest shortfall first
+        let candidates: Vec<(Pubkey, u64, LiquidationReason)> = scan
+            .candidates
+            .iter()
+            .map(|candidate| (candidate.position, candidate.shortfall_usd, candidate.reason))
+            .collect();
+        assert_eq!(
+            candidates,
+            vec![
+                (bankrupt, 5_000_000, LiquidationReason::Bankrupt),
+                (max_leverage_large, 3_000_000, LiquidationReason::MaxLeverageExceeded),
+                (max_leverage_small, 1_000_000, LiquidationReason::MaxLeverageExceeded),
+            ]
+        );
+        let candidate = &scan.candidates[1];
+        assert_eq!(candidate.owner, fixture.snapshot.positions[&max_leverage_large].owner);
+        assert_eq!(candidate.market, market);
+        assert_eq!(candidate.health.equity_usd, 2_000_000);
+    }
+
+    #[test]
+    fn test_find_liquidation_candidates_skipped() {
+        let mut fixture = Fixture::new();
+        let unknown = Pubkey::new_unique();
+        let missing_market = fixture.add_position(unknown, 40_000_000);
+        let market = fixture.add_market(|market| market.pool = unknown);
+        let missing_pool = fixture.add_position(market, 40_000_000);
+        let market = fixture.add_market(|market| market.collateral_custody = unknown);
+        let missing_custody = fixture.add_position(market, 40_000_000);
+        let target_custody = fixture.target_custody;
+        fixture.prices.remove(&target_custody);
+        let missing_price = fixture.add_position(fixture.market, 40_000_000);
+
+        let scan = fixture.scan();
+        assert!(scan.candidates.is_empty());
+        let mut expected = vec![
+            (missing_market, CompError::MissingAccount.into()),
+            (missing_pool, CompError::MissingAccount.into()),
+            (missing_custody, CompError::MissingAccount.into()),
+            (missing_price, CompError::InvalidOraclePrice.into()),
+        ];
+        expected.sort_by_key(|(key, _): &(Pubkey, Error)| *key);
+        assert_eq!(scan.skipped, expected);
+    }
+
+    #[test]
+    fn test_find_liquidation_candidates_permissions() {
+        let mut fixture = Fixture::new();
+        let market = fixture.market;
+        fixture.add_position(market, 40_000_000);
+        assert_eq!(fixture.scan().candidates.len(), 1);
+
+        for perpetuals in fixture.snapshot.perpetuals.values_mut() {
+            perpetuals.permissions.allow_liquidation = false;
+        }
+        assert!(fixture.scan().candidates.is_empty());
+        for perpetuals in fixture.snapshot.perpetuals.values_mut() {
+            perpetuals.permissions.allow_liquidation = true;
+        }
+
+        let pool = fixture.pool;
+        fixture.snapshot.pools.get_mut(&pool).unwrap().permissions.allow_liquidation = false;
+        assert!(fixture.scan().candidates.is_empty());
+        fixture.snapshot.pools.get_mut(&pool).unwrap().permissions.allow_liquidation = true;
+
+        let target_custody = fixture.target_custody;
+        fixture.snapshot.custodies.get_mut(&target_custody).unwrap().permissions.allow_liquidation = false;
+        let scan = fixture.scan();
+        assert!(scan.candidates.is_empty() && scan.skipped.is_empty());
+    }
+
+    #[test]
+    fn test_find_liquidation_candidates_perpetuals() {
+        let mut fixture = Fixture::new();
+        fixture.snapshot.perpetuals.insert(Pubkey::new_unique(), Perpetuals::default());
+        assert_eq!(
+            find_liquidation_candidates(&fixture.snapshot, &fixture.prices, 0).unwrap_err(),
+            CompError::MultiplePerpetuals.into()
+        );
+        fixture.snapshot.perpetuals.clear();
+        assert_eq!(
+            find_liquidation_candidates(&fixture.snapshot, &fixture.prices, 0).unwrap_err(),
+            CompError::MissingPerpetuals.into()
+        );
+    }
+}
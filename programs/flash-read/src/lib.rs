@@ -4,6 +4,23 @@ use anchor_spl::token::Mint;
 pub mod states;
 pub mod math;
 pub mod error;
+pub mod valuation;
+pub mod snapshot;
+pub mod pda;
+pub mod position;
+pub mod fees;
+pub mod swap;
+pub mod liquidity;
+pub mod oracle;
+pub mod liquidation;
+pub mod trigger;
+pub mod keeper;
+pub mod simulator;
+pub mod discount;
+pub mod voltage;
+pub mod staking;
+pub mod compounding;
+pub mod capacity;
 pub use states::*;
 
 #[cfg(feature = "mainnet")]
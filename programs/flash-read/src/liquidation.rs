@@ -0,0 +1,361 @@
+//! Bankruptcy and liquidation prices of positions.
+
+use {
+    crate::{math, position::PositionContext, states::*},
+    anchor_lang::prelude::*,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct LiquidationPrices {
+    // Exit price at which the position equity net of fees drops to zero
+    pub bankruptcy_price: OraclePrice,
+    // Exit price at which the position equity drops to the maintenance margin
+    pub liquidation_price: OraclePrice,
+}
+
+/// Returns the exit prices at which `position` goes bankrupt and becomes liquidatable.
+///
+/// The collateral is valued by `PositionContext::get_collateral_usd` at `collateral_price`,
+/// the current price of the collateral custody token, as done by `get_position_health`. For
+/// markets flagged with `correlation` the collateral value is assumed to move along with
+/// `target_price`, otherwise it stays fixed. Both prices are expressed with the exponent of
+/// `position.entry_price`. Positions without size, and prices the position equity never
+/// reaches, get `OraclePrice::NIL_PRICE`.
+pub fn get_liquidation_prices(
+    context: &PositionContext,
+    position: &Position,
+    target_price: &OraclePrice,
+    collateral_price: &OraclePrice,
+    curtime: i64,
+) -> Result<LiquidationPrices> {
+    if position.size_amount == 0 {
+        return Ok(LiquidationPrices::default());
+    }
+
+    let collateral_usd = context.get_collateral_usd(position, collateral_price)?;
+
+    let close_fee_usd = context
+        .pool
+        .get_fee_amount(context.target_custody.fees.close_position, position.size_usd)?;
+    let lock_fee_usd = context.collateral_custody.get_lock_fee_usd(position, curtime)?;
+    let fees_usd = math::checked_add(
+        math::checked_add(close_fee_usd, lock_fee_usd)?,
+        position.unsettled_fees_usd,
+    )?;
+
+    let max_leverage = context.target_custody.pricing.max_leverage;
+    let maintenance_margin_usd = if max_leverage > 0 {
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(position.size_usd as u128, Perpetuals::BPS_POWER)?,
+            max_leverage as u128,
+        )?)?
+    } else {
+        0
+    };
+
+    let liquidation_usd = math::checked_add(fees_usd, maintenance_margin_usd)?;
+    if context.market.correlation {
+        Ok(LiquidationPrices {
+            bankruptcy_price: get_correlated_price_at_equity(
+                position,
+                context.market.side,
+                collateral_usd,
+                target_price,
+                fees_usd,
+            )?,
+            liquidation_price: get_correlated_price_at_equity(
+                position,
+                context.market.side,
+                collateral_usd,
+                target_price,
+                liquidation_usd,
+            )?,
+        })
+    } else {
+        Ok(LiquidationPrices {
+            bankruptcy_price: get_price_at_equity(position, context.market.side, collateral_usd, fees_usd)?,
+            liquidation_price: get_price_at_equity(
+                position,
+                context.market.side,
+                collateral_usd,
+                liquidation_usd,
+            )?,
+        })
+    }
+}
+
+// Returns the exit price at which the collateral plus the unrealized PnL equals required_usd
+fn get_price_at_equity(
+    position: &Position,
+    side: Side,
+    collateral_usd: u64,
+    required_usd: u64,
+) -> Result<OraclePrice> {
+    let entry_price = position.entry_price;
+    let (pnl_usd, in_profit) = if collateral_usd >= required_usd {
+        // Nominally solvent, the position can absorb this much loss
+        (math::checked_sub(collateral_usd, required_usd)?, false)
+    } else {
+        // Nominally insolvent, the position needs this much profit to cover its liabilities
+        (math::checked_sub(required_usd, collateral_usd)?, true)
+    };
+
+    let price_diff = math::checked_decimal_div(
+        pnl_usd,
+        -(Perpetuals::USD_DECIMALS as i32),
+        position.size_amount,
+        -(position.size_decimals as i32),
+        entry_price.exponent,
+    )?;
+
+    // Longs profit from a price rise and shorts from a price drop
+    let price = if (side == Side::Long) == in_profit {
+        math::checked_add(entry_price.price, price_diff)?
+    } else {
+        entry_price.price.saturating_sub(price_diff)
+    };
+
+    Ok(OraclePrice::new(price, entry_price.exponent))
+}
+
+// Same as get_price_at_equity, with the collateral value moving proportionally to the target
+// price. With C the collateral value at target price P, S the position size valued at entry
+// price E and R the required equity, the equity at exit price p is:
+//   long:  C * p / P + S * p / E - S
+//   short: C * p / P - S * p / E + S
+fn get_correlated_price_at_equity(
+    position: &Position,
+    side: Side,
+    collateral_usd: u64,
+    target_price: &OraclePrice,
+    required_usd: u64,
+) -> Result<OraclePrice> {
+    let entry_price = position.entry_price;
+    let current_price = target_price.scale_to_exponent(entry_price.exponent)?.price as i128;
+    if current_price == 0 || entry_price.price == 0 {
+        return Ok(OraclePrice::NIL_PRICE);
+    }
+    let entry = entry_price.price as i128;
+    let size_usd = entry_price.get_asset_amount_usd(position.size_amount, position.size_decimals)? as i128;
+    let collateral_usd = collateral_usd as i128;
+    let required_usd = required_usd as i128;
+
+    let (numerator, denominator) = if side == Side::Long {
+        (
+            math::checked_add(required_usd, size_usd)?,
+            math::checked_add(
+                math::checked_mul(collateral_usd, entry)?,
+                math::checked_mul(size_usd, current_price)?,
+            )?,
+        )
+    } else {
+        (
+            math::checked_sub(required_usd, size_usd)?,
+            math::checked_sub(
+                math::checked_mul(collateral_usd, entry)?,
+                math::checked_mul(size_usd, current_price)?,
+            )?,
+        )
+    };
+
+    // The equity doesn't depend on the price or never reaches the required amount
+    if denominator == 0 || (numerator < 0) != (denominator < 0) {
+        return Ok(OraclePrice::NIL_PRICE);
+    }
+
+    let price = math::checked_div(
+        math::checked_mul(
+            math::checked_mul(numerator.abs(), current_price)?,
+            entry,
+        )?,
+        denominator.abs(),
+    )?;
+
+    Ok(OraclePrice::new(math::checked_as_u64(price)?, entry_price.exponent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USDC: (u8, u64) = (6, 1_000_000); // (decimals, price with exponent -6)
+    const BTC: (u8, u64) = (8, 50_000_000_000);
+    const SOL: (u8, u64) = (9, 100_000_000);
+
+    struct Case {
+        name: &'static str,
+        side: Side,
+        correlation: bool,
+        size_amount: u64, // SOL, 9 decimals
+        collateral: (u8, u64),
+        collateral_amount: u64,
+        target_price: u64,
+        max_leverage: u64,
+        // (bankruptcy price, liquidation price) with exponent -6, None for NIL_PRICE
+        expected: Option<(u64, u64)>,
+    }
+
+    // 5 SOL position entered at 100 USD, 0.5 USD close fee, 5 USD maintenance margin at 100x
+    fn case(name: &'static str, side: Side, collateral_amount: u64, expected: Option<(u64, u64)>) -> Case {
+        Case {
+            name,
+            side,
+            correlation: false,
+            size_amount: 5_000_000_000,
+            collateral: USDC,
+            collateral_amount,
+            target_price: 100_000_000,
+            max_leverage: 1_000_000,
+            expected,
+        }
+    }
+
+    #[test]
+    fn test_get_liquidation_prices() {
+        let cases = [
+            Case {
+                size_amount: 0,
+                ..case("zero size", Side::Long, 100_000_000, None)
+            },
+            case("long solvent", Side::Long, 100_000_000, Some((80_100_000, 81_100_000))),
+            case("short solvent", Side::Short, 100_000_000, Some((119_900_000, 118_900_000))),
+            case("long insolvent", Side::Long, 200_000, Some((100_060_000, 101_060_000))),
+            case("short insolvent", Side::Short, 200_000, Some((99_940_000, 98_940_000))),
+            Case {
+                collateral: BTC,
+                ..case("long with 0.002 BTC at 50k", Side::Long, 200_000, Some((80_100_000, 81_100_000)))
+            },
+            Case {
+                collateral: (BTC.0, 25_000_000_000),
+                ..case("long with 0.002 BTC at 25k", Side::Long, 200_000, Some((90_100_000, 91_100_000)))
+            },
+            Case {
+                collateral: BTC,
+                ..case("short with 0.002 BTC at 50k", Side::Short, 200_000, Some((119_900_000, 118_900_000)))
+            },
+            Case {
+                max_leverage: 0,
+                ..case("no max leverage", Side::Long, 100_000_000, Some((80_100_000, 80_100_000)))
+            },
+            Case {
+                correlation: true,
+                collateral: SOL,
+                ..case("correlated long", Side::Long, 1_000_000_000, Some((83_416_666, 84_250_000)))
+            },
+            Case {
+                correlation: true,
+                collateral: SOL,
+                ..case("correlated short", Side::Short, 1_000_000_000, Some((124_875_000, 123_625_000)))
+            },
+            Case {
+                correlation: true,
+                collateral: SOL,
+                ..case("correlated short with collateral equal to size", Side::Short, 5_000_000_000, None)
+            },
+            Case {
+                correlation: true,
+                collateral: SOL,
+                ..case("correlated short with collateral above size", Side::Short, 10_000_000_000, None)
+            },
+            Case {
+                correlation: true,
+                collateral: SOL,
+                target_price: 0,
+                ..case("correlated long without target price", Side::Long, 1_000_000_000, None)
+            },
+        ];
+
+        for case in cases.iter() {
+            let pool = Pool::default();
+            let market = Market {
+                side: case.side,
+                correlation: case.correlation,
+                max_payoff_bps: 10_000,
+                ..Market::default()
+            };
+            let target_custody = Custody {
+                fees: Fees {
+                    close_position: 1_000_000,
+                    ..Fees::default()
+                },
+                pricing: PricingParams {
+                    max_leverage: case.max_leverage,
+                    ..PricingParams::default()
+                },
+                ..Custody::default()
+            };
+            let collateral_custody = Custody::default();
+            let context = PositionContext {
+                pool: &pool,
+                market: &market,
+                target_custody: &target_custody,
+                collateral_custody: &collateral_custody,
+            };
+            // The collateral is worth its current price and profits up to the size are paid
+            let collateral_price = OraclePrice::new(case.collateral.1, -6);
+            let position = Position {
+                entry_price: OraclePrice::new(100_000_000, -6),
+                size_amount: case.size_amount,
+                size_usd: if case.size_amount > 0 { 500_000_000 } else { 0 },
+                size_decimals: 9,
+                locked_amount: collateral_price.get_token_amount(500_000_000, case.collateral.0).unwrap(),
+                locked_decimals: case.collateral.0,
+                collateral_amount: case.collateral_amount,
+                collateral_usd: collateral_price
+                    .get_asset_amount_usd(case.collateral_amount, case.collateral.0)
+                    .unwrap(),
+                collateral_decimals: case.collateral.0,
+                ..Position::default()
+            };
+
+            let prices = get_liquidation_prices(
+                &context,
+                &position,
+                &OraclePrice::new(case.target_price, -6),
+                &collateral_price,
+                0,
+            )
+            .unwrap();
+
+            let expected = match case.expected {
+                Some((bankruptcy_price, liquidation_price)) => LiquidationPrices {
+                    bankruptcy_price: OraclePrice::new(bankruptcy_price, -6),
+                    liquidation_price: OraclePrice::new(liquidation_price, -6),
+                },
+                None => LiquidationPrices::default(),
+            };
+            assert_eq!(prices, expected, "{}", case.name);
+            if case.expected.is_none() {
+                continue;
+            }
+
+            // The position health agrees with the liquidation price, correlated collateral
+            // moving along with the target price
+            let collateral_price_at = |price: &OraclePrice| {
+                if case.correlation {
+                    OraclePrice::new(collateral_price.price * price.price / case.target_price, -6)
+                } else {
+                    collateral_price
+                }
+            };
+            let health = context
+                .get_position_health(
+                    &position,
+                    &prices.liquidation_price,
+                    &collateral_price_at(&prices.liquidation_price),
+                    0,
+                )
+                .unwrap();
+            assert_eq!(health.equity_usd, health.maintenance_margin_usd, "{}", case.name);
+            let health = context
+                .get_position_health(
+                    &position,
+                    &prices.bankruptcy_price,
+                    &collateral_price_at(&prices.bankruptcy_price),
+                    0,
+                )
+                .unwrap();
+            assert_eq!(health.equity_usd, 0, "{}", case.name);
+        }
+    }
+}
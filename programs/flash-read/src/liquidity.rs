@@ -0,0 +1,333 @@
+//! Add and remove liquidity quotes for the staked (SFLP) and compounding (FLP) LP tokens.
+
+use {
+    crate::{
+        error::CompError,
+        fees, math,
+        states::*,
+        valuation::{PoolValue, PoolValuation},
+    },
+    anchor_lang::prelude::*,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct LiquidityQuote {
+    // LP tokens minted when adding, custody tokens returned when removing
+    pub amount_out: u64,
+    // Fee charged in custody tokens
+    pub fee_amount: u64,
+    // Fee rate applied, with implied RATE_DECIMALS
+    pub fee_rate: u64,
+    // Share of the pool AUM held by the custody afterwards (BPS_DECIMALS)
+    pub new_ratio: u64,
+}
+
+// Quotes the SFLP minted for depositing amount_in tokens of pool.custodies[token_id]
+pub fn get_add_liquidity_amount_and_fee(
+    valuation: &PoolValuation,
+    prices: &[OraclePrice],
+    lp_supply: u64,
+    token_id: usize,
+    amount_in: u64,
+) -> Result<LiquidityQuote> {
+    let custody = get_custody(valuation, prices, token_id)?;
+    require!(
+        valuation.pool.permissions.allow_add_liquidity && custody.permissions.allow_add_liquidity,
+        CompError::InstructionNotAllowed
+    );
+    let pool_value = valuation.get_pool_value(prices)?;
+
+    let amount_in_usd = prices[token_id].get_asset_amount_usd(amount_in, custody.decimals)?;
+    let new_ratio = fees::get_token_ratio(
+        math::checked_add(pool_value.custody_aum_usd[token_id], amount_in_usd)?,
+        math::checked_add(pool_value.raw_aum_usd, amount_in_usd)?,
+    )?;
+    let fee_rate = fees::get_ratio_fee(
+        custody.fees.mode,
+        &custody.fees.add_liquidity,
+        &valuation.pool.ratios[token_id],
+        new_ratio,
+        true,
+    )?;
+    let fee_amount = valuation.pool.get_fee_amount(fee_rate, amount_in)?;
+
+    let deposit_usd = prices[token_id]
+        .get_asset_amount_usd(math::checked_sub(amount_in, fee_amount)?, custody.decimals)?;
+    let amount_out = if lp_supply == 0 || pool_value.equity_usd == 0 {
+        // LP_DECIMALS match USD_DECIMALS, the first deposit is minted at 1 USD per LP token
+        deposit_usd
+    } else {
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(deposit_usd as u128, lp_supply as u128)?,
+            pool_value.equity_usd as u128,
+        )?)?
+    };
+
+    Ok(LiquidityQuote {
+        amount_out,
+        fee_amount,
+        fee_rate,
+        new_ratio,
+    })
+}
+
+// Quotes the custody tokens returned for burning lp_amount_in SFLP
+pub fn get_remove_liquidity_amount_and_fee(
+    valuation: &PoolValuation,
+    prices: &[OraclePrice],
+    lp_supply: u64,
+    token_id: usize,
+    lp_amount_in: u64,
+) -> Result<LiquidityQuote> {
+    let custody = get_custody(valuation, prices, token_id)?;
+    require!(
+        valuation.pool.permissions.allow_remove_liquidity
+            && custody.permissions.allow_remove_liquidity,
+        CompError::InstructionNotAllowed
+    );
+    require!(lp_amount_in <= lp_supply, CompError::InsufficientLiquidity);
+    let pool_value = valuation.get_pool_value(prices)?;
+
+    let withdrawal_usd = math::checked_as_u64(math::checked_div(
+        math::checked_mul(lp_amount_in as u128, pool_value.equity_usd as u128)?,
+        lp_supply as u128,
+    )?)?;
+    let new_ratio = get_ratio_after_withdrawal(&pool_value, token_id, withdrawal_usd)?;
+    let fee_rate = fees::get_ratio_fee(
+        custody.fees.mode,
+        &custody.fees.remove_liquidity,
+        &valuation.pool.ratios[token_id],
+        new_ratio,
+        false,
+    )?;
+
+    let amount = prices[token_id].get_token_amount(withdrawal_usd, custody.decimals)?;
+    let fee_amount = valuation.pool.get_fee_amount(fee_rate, amount)?;
+    let amount_out = math::checked_sub(amount, fee_amount)?;
+
+    let available_amount = custody.assets.owned.saturating_sub(custody.assets.locked);
+    require!(amount_out <= available_amount, CompError::InsufficientLiquidity);
+
+    Ok(LiquidityQuote {
+        amount_out,
+        fee_amount,
+        fee_rate,
+        new_ratio,
+    })
+}
+
+// Quotes the FLP minted for depositing amount_in tokens of pool.custodies[token_id]
+pub fn get_add_compounding_liquidity_amount_and_fee(
+    valuation: &PoolValuation,
+    prices: &[OraclePrice],
+    lp_supply: u64,
+    token_id: usize,
+    amount_in: u64,
+) -> Result<LiquidityQuote> {
+    let quote = get_add_liquidity_amount_and_fee(valuation, prices, lp_supply, token_id, amount_in)?;
+    Ok(LiquidityQuote {
+        amount_out: get_compounding_amount(&valuation.pool.compounding_stats, quote.amount_out)?,
+        ..quote
+    })
+}
+
+// Quotes the custody tokens returned for burning compounding_amount_in FLP
+pub fn get_remove_compounding_liquidity_amount_and_fee(
+    valuation: &PoolValuation,
+    prices: &[OraclePrice],
+    lp_supply: u64,
+    token_id: usize,
+    compounding_amount_in: u64,
+) -> Result<LiquidityQuote> {
+    let stats = &valuation.pool.compounding_stats;
+    require!(
+        compounding_amount_in <= stats.total_supply,
+        CompError::InsufficientLiquidity
+    );
+    let lp_amount_in = math::checked_as_u64(math::checked_div(
+        math::checked_mul(compounding_amount_in as u128, stats.active_amount as u128)?,
+        stats.total_supply as u128,
+    )?)?;
+    get_remove_liquidity_amount_and_fee(valuation, prices, lp_supply, token_id, lp_amount_in)
+}
+
+// Converts SFLP into the FLP it is worth, based on the SFLP backing the compounding mint
+fn get_compounding_amount(stats: &CompoundingStats, lp_amount: u64) -> Result<u64> {
+    if stats.active_amount == 0 || stats.total_supply == 0 {
+        return Ok(lp_amount);
+    }
+    math::checked_as_u64(math::checked_div(
+        math::checked_mul(lp_amount as u128, stats.total_supply as u128)?,
+        stats.active_amount as u128,
+    )?)
+}
+
+fn get_ratio_after_withdrawal(pool_value: &PoolValue, token_id: usize, withdrawal_usd: u64) -> Result<u64> {
+    fees::get_token_ratio(
+        pool_value.custody_aum_usd[token_id].saturating_sub(withdrawal_usd),
+        pool_value.raw_aum_usd.saturating_sub(withdrawal_usd),
+    )
+}
+
+fn get_custody<'a>(valuation: &PoolValuation<'a>, prices: &[OraclePrice], token_id: usize) -> Result<&'a Custody> {
+    if prices.len() != valuation.custodies.len() || valuation.pool.ratios.len() != valuation.custodies.len() {
+        return Err(ProgramError::NotEnoughAccountKeys.into());
+    }
+    valuation
+        .custodies
+        .get(token_id)
+        .ok_or_else(|| CompError::UnsupportedCustody.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL: usize = 0;
+    const USDC: usize = 1;
+    // 20k USD of equity over 10k SFLP: 2 USD per SFLP
+    const LP_SUPPLY: u64 = 10_000_000_000;
+
+    fn get_custody(decimals: u8, owned: u64) -> Custody {
+        // 0.1% to add or remove liquidity
+        let fees = RatioFees {
+            target_fee: 1_000_000,
+            ..RatioFees::default()
+        };
+        Custody {
+            decimals,
+            fees: Fees {
+                mode: FeesMode::Fixed,
+                add_liquidity: fees,
+                remove_liquidity: fees,
+                ..Fees::default()
+            },
+            permissions: Permissions {
+                allow_add_liquidity: true,
+                allow_remove_liquidity: true,
+                ..Permissions::default()
+            },
+            assets: Assets {
+                owned,
+                ..Assets::default()
+            },
+            ..Custody::default()
+        }
+    }
+
+    // 100 SOL at 100 USD and 10k USDC, 1.2 SFLP backing each FLP
+    fn get_fixtures() -> (Pool, Vec<Custody>, Vec<OraclePrice>) {
+        let pool = Pool {
+            permissions: Permissions {
+                allow_add_liquidity: true,
+                allow_remove_liquidity: true,
+                ..Permissions::default()
+            },
+            custodies: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+            ratios: vec![TokenRatios::default(); 2],
+            compounding_stats: CompoundingStats {
+                active_amount: 900_000_000,
+                total_supply: 750_000_000,
+                ..CompoundingStats::default()
+            },
+            ..Pool::default()
+        };
+        let custodies = vec![get_custody(9, 100_000_000_000), get_custody(6, 10_000_000_000)];
+        let prices = vec![OraclePrice::new(100_000_000, -6), OraclePrice::new(1_000_000, -6)];
+        (pool, custodies, prices)
+    }
+
+    #[test]
+    fn test_add_liquidity() {
+        let (pool, custodies, prices) = get_fixtures();
+        let valuation = PoolValuation::new(&pool, &custodies, &[]).unwrap();
+
+        // 100 USDC less the 0.1% fee is 99.9 USD, bringing USDC to 10,100 / 20,100 of the pool
+        let quote = get_add_liquidity_amount_and_fee(&valuation, &prices, LP_SUPPLY, USDC, 100_000_000).unwrap();
+        assert_eq!(
+            quote,
+            LiquidityQuote {
+                amount_out: 49_950_000,
+                fee_amount: 100_000,
+                fee_rate: 1_000_000,
+                new_ratio: 5024,
+            }
+        );
+
+        // The first deposit is minted at 1 USD per SFLP
+        let quote = get_add_liquidity_amount_and_fee(&valuation, &prices, 0, USDC, 100_000_000).unwrap();
+        assert_eq!(quote.amount_out, 99_900_000);
+        let quote = get_add_liquidity_amount_and_fee(&valuation, &prices, 0, SOL, 1_000_000_000).unwrap();
+        assert_eq!(quote.amount_out, 99_900_000);
+    }
+
+    #[test]
+    fn test_add_compounding_liquidity() {
+        let (mut pool, custodies, prices) = get_fixtures();
+        let valuation = PoolValuation::new(&pool, &custodies, &[]).unwrap();
+
+        // 49.95 SFLP at 1.2 SFLP per FLP
+        let quote =
+            get_add_compounding_liquidity_amount_and_fee(&valuation, &prices, LP_SUPPLY, USDC, 100_000_000).unwrap();
+        assert_eq!(quote.amount_out, 41_625_000);
+
+        // Without compounding stats FLP is minted 1:1
+        pool.compounding_stats = CompoundingStats::default();
+        let valuation = PoolValuation::new(&pool, &custodies, &[]).unwrap();
+        let quote =
+            get_add_compounding_liquidity_amount_and_fee(&valuation, &prices, LP_SUPPLY, USDC, 100_000_000).unwrap();
+        assert_eq!(quote.amount_out, 49_950_000);
+    }
+
+    #[test]
+    fn test_remove_liquidity() {
+        let (pool, mut custodies, prices) = get_fixtures();
+
+        // 1k SFLP is 2k USDC less the 0.1% fee, leaving USDC at 8,000 / 18,000 of the pool
+        let valuation = PoolValuation::new(&pool, &custodies, &[]).unwrap();
+        let quote =
+            get_remove_liquidity_amount_and_fee(&valuation, &prices, LP_SUPPLY, USDC, 1_000_000_000).unwrap();
+        assert_eq!(
+            quote,
+            LiquidityQuote {
+                amount_out: 1_998_000_000,
+                fee_amount: 2_000_000,
+                fee_rate: 1_000_000,
+                new_ratio: 4444,
+            }
+        );
+
+        assert_eq!(
+            get_remove_liquidity_amount_and_fee(&valuation, &prices, LP_SUPPLY, USDC, LP_SUPPLY + 1).unwrap_err(),
+            CompError::InsufficientLiquidity.into()
+        );
+
+        // Only owned minus locked tokens can be withdrawn
+        custodies[USDC].assets.locked = custodies[USDC].assets.owned - 1_998_000_000;
+        let valuation = PoolValuation::new(&pool, &custodies, &[]).unwrap();
+        assert!(get_remove_liquidity_amount_and_fee(&valuation, &prices, LP_SUPPLY, USDC, 1_000_000_000).is_ok());
+        custodies[USDC].assets.locked += 1;
+        let valuation = PoolValuation::new(&pool, &custodies, &[]).unwrap();
+        assert_eq!(
+            get_remove_liquidity_amount_and_fee(&valuation, &prices, LP_SUPPLY, USDC, 1_000_000_000).unwrap_err(),
+            CompError::InsufficientLiquidity.into()
+        );
+    }
+
+    #[test]
+    fn test_remove_compounding_liquidity() {
+        let (pool, custodies, prices) = get_fixtures();
+        let valuation = PoolValuation::new(&pool, &custodies, &[]).unwrap();
+
+        // 100 FLP is 120 SFLP, worth 240 USDC less the 0.1% fee
+        let quote =
+            get_remove_compounding_liquidity_amount_and_fee(&valuation, &prices, LP_SUPPLY, USDC, 100_000_000).unwrap();
+        assert_eq!(quote.amount_out, 239_760_000);
+
+        assert_eq!(
+            get_remove_compounding_liquidity_amount_and_fee(&valuation, &prices, LP_SUPPLY, USDC, 750_000_001)
+                .unwrap_err(),
+            CompError::InsufficientLiquidity.into()
+        );
+    }
+}
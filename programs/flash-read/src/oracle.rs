@@ -0,0 +1,239 @@
+//! Selection between the primary and backup oracles of a custody.
+//!
+//! The primary oracle is the one matching `custody.oracle.oracle_type`, the other one
+//! serves as backup and is accepted up to `custody.oracle.max_backup_age_sec`.
+
+use {
+    crate::{error::CompError, states::*},
+    anchor_lang::prelude::*,
+    pyth_solana_receiver_sdk::price_update::PriceUpdateV2,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Debug)]
+pub enum OracleSource {
+    // CustomOracle account at oracle.int_oracle_account
+    Internal,
+    // Pyth PriceUpdateV2 account at oracle.ext_oracle_account
+    External,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Debug)]
+pub struct ResolvedPrice {
+    pub min_price: OraclePrice,
+    pub max_price: OraclePrice,
+    pub volatility_flag: bool,
+    pub source: OracleSource,
+    // Set when the primary oracle was unusable and the backup was used instead
+    pub is_backup: bool,
+}
+
+// Resolves the custody price from whichever of the decoded oracle accounts are available
+pub fn resolve_custody_price(
+    custody: &Custody,
+    custom_oracle: Option<&CustomOracle>,
+    price_update: Option<&PriceUpdateV2>,
+    current_time: i64,
+) -> Result<ResolvedPrice> {
+    let primary = match custody.oracle.oracle_type {
+        OracleType::Custom => OracleSource::Internal,
+        OracleType::Pyth => OracleSource::External,
+        OracleType::None => return err!(CompError::InvalidOraclePrice),
+    };
+    let backup = if primary == OracleSource::Internal {
+        OracleSource::External
+    } else {
+        OracleSource::Internal
+    };
+
+    if let Ok(price) = fetch_price(
+        custody,
+        primary,
+        custom_oracle,
+        price_update,
+        &custody.oracle,
+        current_time,
+    ) {
+        return Ok(price);
+    }
+
+    let backup_params = OracleParams {
+        max_price_age_sec: custody.oracle.max_backup_age_sec,
+        ..custody.oracle
+    };
+    let price = fetch_price(
+        custody,
+        backup,
+        custom_oracle,
+        price_update,
+        &backup_params,
+        current_time,
+    )?;
+    Ok(ResolvedPrice {
+        is_backup: true,
+        ..price
+    })
+}
+
+// On-chain counterpart of resolve_custody_price, an oracle account that doesn't decode is
+// treated as unavailable
+pub fn resolve_custody_price_from_accounts(
+    custody: &Custody,
+    int_oracle_account: &AccountInfo,
+    ext_oracle_account: &AccountInfo,
+    current_time: i64,
+) -> Result<ResolvedPrice> {
+    require_keys_eq!(int_oracle_account.key(), custody.oracle.int_oracle_account);
+    require_keys_eq!(ext_oracle_account.key(), custody.oracle.ext_oracle_account);
+
+    let custom_oracle = Account::<CustomOracle>::try_from(int_oracle_account).ok();
+    let price_update = Account::<PriceUpdateV2>::try_from(ext_oracle_account).ok();
+
+    resolve_custody_price(
+        custody,
+        custom_oracle.as_deref(),
+        price_update.as_deref(),
+        current_time,
+    )
+}
+
+fn fetch_price(
+    custody: &Custody,
+    source: OracleSource,
+    custom_oracle: Option<&CustomOracle>,
+    price_update: Option<&PriceUpdateV2>,
+    oracle_params: &OracleParams,
+    current_time: i64,
+) -> Result<ResolvedPrice> {
+    let (min_price, max_price, volatility_flag) = match source {
+        OracleSource::Internal => OraclePrice::fetch_from_custom_oracle(
+            custom_oracle.ok_or(CompError::InvalidOraclePrice)?,
+            oracle_params,
+            current_time,
+            custody.is_stable,
+        )?,
+        OracleSource::External => OraclePrice::fetch_from_pyth_oracle(
+            price_update.ok_or(CompError::InvalidOraclePrice)?,
+            oracle_params,
+            current_time,
+            custody.is_stable,
+        )?,
+    };
+    Ok(ResolvedPrice {
+        min_price,
+        max_price,
+        volatility_flag,
+        source,
+        is_backup: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        pyth_solana_receiver_sdk::price_update::VerificationLevel,
+        pythnet_sdk::messages::PriceFeedMessage,
+    };
+
+    // Both oracles publish at 100, Pyth at 100 USD and the custom oracle at 101 USD
+    fn get_oracles() -> (CustomOracle, PriceUpdateV2) {
+        let custom_oracle = CustomOracle {
+            price: 101_000_000,
+            expo: -6,
+            ema: 101_000_000,
+            publish_time: 100,
+            ..CustomOracle::default()
+        };
+        let price_update = PriceUpdateV2 {
+            write_authority: Pubkey::default(),
+            verification_level: VerificationLevel::Full,
+            price_message: PriceFeedMessage {
+                feed_id: [0; 32],
+                price: 100_000_000,
+                conf: 0,
+                exponent: -6,
+                publish_time: 100,
+                prev_publish_time: 99,
+                ema_price: 100_000_000,
+                ema_conf: 0,
+            },
+            posted_slot: 0,
+        };
+        (custom_oracle, price_update)
+    }
+
+    // Prices are valid for 10 seconds, 60 seconds when used as backup
+    fn get_custody(oracle_type: OracleType) -> Custody {
+        Custody {
+            oracle: OracleParams {
+                oracle_type,
+                max_divergence_bps: 100,
+                max_conf_bps: 100,
+                max_price_age_sec: 10,
+                max_backup_age_sec: 60,
+                ..OracleParams::default()
+            },
+            ..Custody::default()
+        }
+    }
+
+    fn get_resolved_price(price: u64, source: OracleSource, is_backup: bool) -> ResolvedPrice {
+        ResolvedPrice {
+            min_price: OraclePrice::new(price, -6),
+            max_price: OraclePrice::new(price, -6),
+            volatility_flag: false,
+            source,
+            is_backup,
+        }
+    }
+
+    #[test]
+    fn test_resolve_custody_price() {
+        let (custom_oracle, price_update) = get_oracles();
+        let pyth_custody = get_custody(OracleType::Pyth);
+        let custom_custody = get_custody(OracleType::Custom);
+
+        // Fresh primaries are used
+        assert_eq!(
+            resolve_custody_price(&pyth_custody, Some(&custom_oracle), Some(&price_update), 105).unwrap(),
+            get_resolved_price(100_000_000, OracleSource::External, false)
+        );
+        assert_eq!(
+            resolve_custody_price(&custom_custody, Some(&custom_oracle), Some(&price_update), 105).unwrap(),
+            get_resolved_price(101_000_000, OracleSource::Internal, false)
+        );
+
+        // Stale or missing primaries fall back to a backup younger than max_backup_age_sec
+        assert_eq!(
+            resolve_custody_price(&pyth_custody, Some(&custom_oracle), Some(&price_update), 160).unwrap(),
+            get_resolved_price(101_000_000, OracleSource::Internal, true)
+        );
+        assert_eq!(
+            resolve_custody_price(&custom_custody, Some(&custom_oracle), Some(&price_update), 111).unwrap(),
+            get_resolved_price(100_000_000, OracleSource::External, true)
+        );
+        assert_eq!(
+            resolve_custody_price(&pyth_custody, Some(&custom_oracle), None, 105).unwrap(),
+            get_resolved_price(101_000_000, OracleSource::Internal, true)
+        );
+    }
+
+    #[test]
+    fn test_resolve_custody_price_errors() {
+        let (custom_oracle, price_update) = get_oracles();
+        let cases = [
+            ("backup too old", get_custody(OracleType::Pyth), Some(&custom_oracle), Some(&price_update), 161),
+            ("no backup", get_custody(OracleType::Pyth), None, Some(&price_update), 111),
+            ("no oracles", get_custody(OracleType::Custom), None, None, 105),
+            ("no oracle type", get_custody(OracleType::None), Some(&custom_oracle), Some(&price_update), 105),
+        ];
+        for (name, custody, custom_oracle, price_update, current_time) in cases.iter() {
+            assert_eq!(
+                resolve_custody_price(custody, *custom_oracle, *price_update, *current_time).unwrap_err(),
+                CompError::InvalidOraclePrice.into(),
+                "{}",
+                name
+            );
+        }
+    }
+}
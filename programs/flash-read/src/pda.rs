@@ -0,0 +1,66 @@
+//! Program derived addresses of the perpetuals program accounts.
+//!
+//! Every function takes the id of the program owning the accounts, e.g.
+//! `flash_compute::FLASH_PROGRAM` for the mainnet or devnet deployment.
+
+use {
+    crate::states::Side,
+    anchor_lang::prelude::*,
+};
+
+pub fn find_perpetuals_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"perpetuals"], program_id)
+}
+
+pub fn find_pool_address(name: &str, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"pool", name.as_bytes()], program_id)
+}
+
+pub fn find_lp_token_mint_address(pool: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"lp_token_mint", pool.as_ref()], program_id)
+}
+
+pub fn find_custody_address(pool: &Pubkey, mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"custody", pool.as_ref(), mint.as_ref()], program_id)
+}
+
+pub fn find_market_address(
+    target_custody: &Pubkey,
+    collateral_custody: &Pubkey,
+    side: Side,
+    program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"market",
+            target_custody.as_ref(),
+            collateral_custody.as_ref(),
+            &[side as u8],
+        ],
+        program_id,
+    )
+}
+
+pub fn find_position_address(owner: &Pubkey, market: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"position", owner.as_ref(), market.as_ref()], program_id)
+}
+
+pub fn find_compounding_mint_address(pool: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"compounding_token_mint", pool.as_ref()], program_id)
+}
+
+// Vault holding the LP tokens staked in the pool (`pool.staked_lp_vault`)
+pub fn find_staked_lp_vault_address(pool: &Pubkey, lp_mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"staked_lp_token_account", pool.as_ref(), lp_mint.as_ref()],
+        program_id,
+    )
+}
+
+// Vault holding the LP tokens backing the compounding mint (`pool.compounding_lp_vault`)
+pub fn find_compounding_lp_vault_address(pool: &Pubkey, lp_mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"compounding_token_account", pool.as_ref(), lp_mint.as_ref()],
+        program_id,
+    )
+}
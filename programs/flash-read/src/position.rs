@@ -0,0 +1,173 @@
+//! Position level analytics built on top of the pool, market and custody states.
+
+use {
+    crate::{math, states::*},
+    anchor_lang::prelude::*,
+};
+
+/// Accounts a position is evaluated against.
+pub struct PositionContext<'a> {
+    pub pool: &'a Pool,
+    pub market: &'a Market,
+    pub target_custody: &'a Custody,
+    pub collateral_custody: &'a Custody,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct PositionHealth {
+    pub profit_usd: u64,
+    pub loss_usd: u64,
+    pub lock_fee_usd: u64,
+    pub close_fee_usd: u64,
+    pub unsettled_fees_usd: u64,
+    // Collateral plus unrealized PnL net of all fees
+    pub equity_usd: u64,
+    // Equity required to stay below pricing.max_leverage
+    pub maintenance_margin_usd: u64,
+    pub leverage: u64, // BPS_DECIMALS
+    pub max_leverage: u64, // BPS_DECIMALS
+    pub margin_ratio_bps: u64, // equity_usd over maintenance_margin_usd
+    // Adverse move of the exit price, relative to the position size, that triggers liquidation
+    pub liquidation_distance_bps: u64,
+}
+
+impl PositionHealth {
+    // Positions of custodies without a max leverage are never liquidatable
+    pub fn is_liquidatable(&self) -> bool {
+        self.max_leverage > 0 && self.leverage > self.max_leverage
+    }
+}
+
+impl<'a> PositionContext<'a> {
+    pub fn get_exit_price(
+        &self,
+        position: &Position,
+        min_price: &OraclePrice,
+        max_price: &OraclePrice,
+    ) -> Result<OraclePrice> {
+        self.pool.get_exit_price(
+            min_price,
+            max_price,
+            self.market.side,
+            self.target_custody.get_trade_spread(position.size_usd)?,
+        )
+    }
+
+    // Value of the position collateral. It stays at position.collateral_usd, except in
+    // correlated markets where the collateral tracks the target token and is valued at
+    // collateral_price.
+    pub fn get_collateral_usd(&self, position: &Position, collateral_price: &OraclePrice) -> Result<u64> {
+        if self.market.correlation {
+            collateral_price.get_asset_amount_usd(position.collateral_amount, position.collateral_decimals)
+        } else {
+            Ok(position.collateral_usd)
+        }
+    }
+
+    pub fn get_position_health(
+        &self,
+        position: &Position,
+        exit_price: &OraclePrice,
+        collateral_price: &OraclePrice,
+        curtime: i64,
+    ) -> Result<PositionHealth> {
+        // Losses are capped by the collateral value the equity is based on
+        let position = &Position {
+            collateral_usd: self.get_collateral_usd(position, collateral_price)?,
+            ..position.clone()
+        };
+        let (profit_usd, loss_usd) = position.get_pnl_usd(
+            exit_price,
+            self.market.side,
+            collateral_price,
+            self.market.max_payoff_bps,
+        )?;
+        let lock_fee_usd = self.collateral_custody.get_lock_fee_usd(position, curtime)?;
+        let close_fee_usd = self
+            .pool
+            .get_fee_amount(self.target_custody.fees.close_position, position.size_usd)?;
+
+        let fees_usd = math::checked_add(
+            math::checked_add(lock_fee_usd, close_fee_usd)?,
+            position.unsettled_fees_usd,
+        )?;
+        let equity_usd = math::checked_add(position.collateral_usd, profit_usd)?
+            .saturating_sub(math::checked_add(loss_usd, fees_usd)?);
+
+        let max_leverage = self.target_custody.pricing.max_leverage;
+        let maintenance_margin_usd = if max_leverage > 0 {
+            math::checked_as_u64(math::checked_div(
+                math::checked_mul(position.size_usd as u128, Perpetuals::BPS_POWER)?,
+                max_leverage as u128,
+            )?)?
+        } else {
+            0
+        };
+
+        let leverage = if equity_usd > 0 {
+            math::checked_as_u64(math::checked_div(
+                math::checked_mul(position.size_usd as u128, Perpetuals::BPS_POWER)?,
+                equity_usd as u128,
+            )?)?
+        } else if position.size_usd > 0 {
+            u64::MAX
+        } else {
+            0
+        };
+
+        let margin_ratio_bps = if maintenance_margin_usd > 0 {
+            math::checked_as_u64(math::checked_div(
+                math::checked_mul(equity_usd as u128, Perpetuals::BPS_POWER)?,
+                maintenance_margin_usd as u128,
+            )?)?
+        } else {
+            u64::MAX
+        };
+
+        // PnL is linear in the exit price, so the excess equity over the position size
+        // is the relative price move the position can absorb
+        let liquidation_distance_bps = if position.size_usd > 0 {
+            math::checked_as_u64(math::checked_div(
+                math::checked_mul(
+                    equity_usd.saturating_sub(maintenance_margin_usd) as u128,
+                    Perpetuals::BPS_POWER,
+                )?,
+                position.size_usd as u128,
+            )?)?
+        } else {
+            0
+        };
+
+        Ok(PositionHealth {
+            profit_usd,
+            loss_usd,
+            lock_fee_usd,
+            close_fee_usd,
+            unsettled_fees_usd: position.unsettled_fees_usd,
+            equity_usd,
+            maintenance_margin_usd,
+            leverage,
+            max_leverage,
+            margin_ratio_bps,
+            liquidation_distance_bps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_liquidatable() {
+        let health = |leverage, max_leverage| PositionHealth {
+            leverage,
+            max_leverage,
+            ..PositionHealth::default()
+        };
+        assert!(!health(500_000, 1_000_000).is_liquidatable());
+        assert!(!health(1_000_000, 1_000_000).is_liquidatable());
+        assert!(health(1_000_001, 1_000_000).is_liquidatable());
+        assert!(!health(u64::MAX, 0).is_liquidatable());
+    }
+}
@@ -0,0 +1,787 @@
+//! Simulation of trading instructions, to validate orders before they are sent.
+
+use {
+    crate::{
+        error::CompError,
+        liquidation,
+        math,
+        oracle::ResolvedPrice,
+        position::PositionContext,
+        states::*,
+    },
+    anchor_lang::prelude::*,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct OpenPositionQuote {
+    // Entry price, and open and volatility fees in collateral tokens
+    pub prices_and_fee: NewPositionPricesAndFee,
+    pub size_amount: u64,
+    // Collateral left once the fees are paid
+    pub collateral_usd: u64,
+    pub leverage: u64, // BPS_DECIMALS
+    pub locked_amount: u64,
+    pub locked_usd: u64,
+    pub liquidation_price: OraclePrice,
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct ClosePositionQuote {
+    pub exit_price: OraclePrice,
+    // Size actually closed, capped by the position size
+    pub size_delta_usd: u64,
+    // PnL realized on the closed size
+    pub profit_usd: u64,
+    pub loss_usd: u64,
+    pub close_fee_usd: u64,
+    // Lock fee accrued by the whole position, settled on any size change
+    pub lock_fee_usd: u64,
+    pub unsettled_fees_usd: u64,
+    // Collateral and profit returned to the owner, net of losses and fees
+    pub returned_usd: u64,
+    pub returned_amount: u64,
+    // Position left after the change, empty on a full close
+    pub residual_position: Position,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct CollateralQuote {
+    // Collateral deposited or withdrawn
+    pub collateral_amount: u64,
+    pub collateral_usd: u64,
+    // Resulting position state
+    pub position_collateral_usd: u64,
+    pub leverage: u64, // BPS_DECIMALS
+    pub liquidation_price: OraclePrice,
+}
+
+/// Simulates opening a position of `size_usd` backed by `collateral_amount` tokens of the
+/// collateral custody.
+///
+/// The volatility fee (`vb_fee_amount`) is only charged while the target oracle raises its
+/// volatility flag. Collateral is valued at its min price and fees are converted to collateral
+/// tokens at its max price.
+pub fn simulate_open_position(
+    context: &PositionContext,
+    target_price: &ResolvedPrice,
+    collateral_price: &ResolvedPrice,
+    size_usd: u64,
+    collateral_amount: u64,
+    curtime: i64,
+) -> Result<OpenPositionQuote> {
+    let pool = context.pool;
+    let market = context.market;
+    let target_custody = context.target_custody;
+    let collateral_custody = context.collateral_custody;
+    require!(
+        pool.permissions.allow_open_position
+            && target_custody.permissions.allow_open_position
+            && market.permissions.allow_open_position,
+        CompError::InstructionNotAllowed
+    );
+
+    let entry_price = pool.get_entry_price(
+        &target_price.min_price,
+        &target_price.max_price,
+        market.side,
+        target_custody.get_trade_spread(size_usd)?,
+    )?;
+    let size_amount = entry_price.get_token_amount(size_usd, target_custody.decimals)?;
+
+    let entry_fee_usd = pool.get_fee_amount(target_custody.fees.open_position, size_usd)?;
+    let vb_fee_usd = if target_price.volatility_flag {
+        pool.get_fee_amount(target_custody.fees.volatility, size_usd)?
+    } else {
+        0
+    };
+    let entry_fee_amount = collateral_price
+        .max_price
+        .get_token_amount(entry_fee_usd, collateral_custody.decimals)?;
+    let vb_fee_amount = collateral_price
+        .max_price
+        .get_token_amount(vb_fee_usd, collateral_custody.decimals)?;
+
+    let fee_amount = math::checked_add(entry_fee_amount, vb_fee_amount)?;
+    require!(collateral_amount > fee_amount, CompError::InsufficientCollateral);
+    let net_collateral_amount = math::checked_sub(collateral_amount, fee_amount)?;
+    let collateral_usd = collateral_price
+        .min_price
+        .get_asset_amount_usd(net_collateral_amount, collateral_custody.decimals)?;
+    require!(
+        collateral_usd >= target_custody.pricing.min_collateral_usd,
+        CompError::InsufficientCollateral
+    );
+
+    let leverage = math::checked_as_u64(math::checked_div(
+        math::checked_mul(size_usd as u128, Perpetuals::BPS_POWER)?,
+        collateral_usd as u128,
+    )?)?;
+    require!(
+        leverage >= target_custody.pricing.min_initial_leverage,
+        CompError::MinLeverage
+    );
+    require!(
+        leverage <= target_custody.pricing.max_initial_leverage,
+        CompError::MaxLeverage
+    );
+
+    // The collateral custody locks the size of the position to pay out profits
+    let locked_usd = size_usd;
+    let locked_amount = collateral_price
+        .min_price
+        .get_token_amount(locked_usd, collateral_custody.decimals)?;
+    require!(
+        locked_usd <= target_custody.pricing.max_position_locked_usd,
+        CompError::MaxPositionLocked
+    );
+    require!(
+        locked_amount
+            <= collateral_custody
+                .assets
+                .owned
+                .saturating_sub(collateral_custody.assets.locked),
+        CompError::InsufficientLiquidity
+    );
+
+    let position = Position {
+        open_time: curtime,
+        update_time: curtime,
+        entry_price,
+        size_amount,
+        size_usd,
+        locked_amount,
+        locked_usd,
+        collateral_amount: net_collateral_amount,
+        collateral_usd,
+        cumulative_lock_fee_snapshot: collateral_custody.get_cumulative_lock_fee(curtime)?,
+        size_decimals: target_custody.decimals,
+        locked_decimals: collateral_custody.decimals,
+        collateral_decimals: collateral_custody.decimals,
+        ..Position::default()
+    };
+    let liquidation_price = liquidation::get_liquidation_prices(
+        context,
+        &position,
+        &target_price.min_price,
+        &collateral_price.min_price,
+        curtime,
+    )?
+    .liquidation_price;
+
+    Ok(OpenPositionQuote {
+        prices_and_fee: NewPositionPricesAndFee {
+            entry_price,
+            entry_fee_amount,
+            vb_fee_amount,
+        },
+        size_amount,
+        collateral_usd,
+        leverage,
+        locked_amount,
+        locked_usd,
+        liquidation_price,
+    })
+}
+
+/// Simulates closing `size_delta_usd` of `position`, or the whole position if the delta
+/// covers its size.
+///
+/// Collateral is released in proportion to the closed size. Fees exceeding the released
+/// collateral and realized profit are taken from the residual collateral. Profit is valued at
+/// the collateral min price and returned tokens are converted at its max price.
+pub fn simulate_close_position(
+    context: &PositionContext,
+    position: &Position,
+    target_price: &ResolvedPrice,
+    collateral_price: &ResolvedPrice,
+    size_delta_usd: u64,
+    curtime: i64,
+) -> Result<ClosePositionQuote> {
+    let pool = context.pool;
+    let market = context.market;
+    let target_custody = context.target_custody;
+    let collateral_custody = context.collateral_custody;
+    require!(
+        size_delta_usd > 0 && position.size_usd > 0,
+        CompError::InvalidSizeDelta
+    );
+
+    let size_delta_usd = std::cmp::min(size_delta_usd, position.size_usd);
+    if size_delta_usd == position.size_usd {
+        require!(
+            pool.permissions.allow_close_position
+                && target_custody.permissions.allow_close_position
+                && market.permissions.allow_close_position,
+            CompError::InstructionNotAllowed
+        );
+    } else {
+        require!(
+            pool.permissions.allow_size_change
+                && target_custody.permissions.allow_size_change
+                && market.permissions.allow_size_change,
+            CompError::InstructionNotAllowed
+        );
+    }
+    let get_share = |amount: u64| -> Result<u64> {
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(amount as u128, size_delta_usd as u128)?,
+            position.size_usd as u128,
+        )?)
+    };
+
+    let exit_price = context.get_exit_price(position, &target_price.min_price, &target_price.max_price)?;
+    let (profit_usd, loss_usd) = position.get_pnl_usd(
+        &exit_price,
+        market.side,
+        &collateral_price.min_price,
+        market.max_payoff_bps,
+    )?;
+    let profit_usd = get_share(profit_usd)?;
+    let loss_usd = get_share(loss_usd)?;
+
+    let close_fee_usd = pool.get_fee_amount(target_custody.fees.close_position, size_delta_usd)?;
+    let lock_fee_usd = collateral_custody.get_lock_fee_usd(position, curtime)?;
+    let fees_usd = math::checked_add(
+        math::checked_add(close_fee_usd, lock_fee_usd)?,
+        position.unsettled_fees_usd,
+    )?;
+
+    let released_collateral_usd = get_share(position.collateral_usd)?;
+    let released_collateral_amount = get_share(position.collateral_amount)?;
+    let credit_usd = math::checked_add(released_collateral_usd, profit_usd)?;
+    let debit_usd = math::checked_add(loss_usd, fees_usd)?;
+    let returned_usd = credit_usd.saturating_sub(debit_usd);
+    let shortfall_usd = debit_usd.saturating_sub(credit_usd);
+    let returned_amount = collateral_price
+        .max_price
+        .get_token_amount(returned_usd, collateral_custody.decimals)?;
+
+    let residual_position = if size_delta_usd == position.size_usd {
+        Position::default()
+    } else {
+        let shortfall_amount = collateral_price
+            .min_price
+            .get_token_amount(shortfall_usd, collateral_custody.decimals)?;
+        Position {
+            update_time: curtime,
+            size_amount: math::checked_sub(position.size_amount, get_share(position.size_amount)?)?,
+            size_usd: math::checked_sub(position.size_usd, size_delta_usd)?,
+            locked_amount: math::checked_sub(position.locked_amount, get_share(position.locked_amount)?)?,
+            locked_usd: math::checked_sub(position.locked_usd, get_share(position.locked_usd)?)?,
+            collateral_amount: math::checked_sub(position.collateral_amount, released_collateral_amount)?
+                .saturating_sub(shortfall_amount),
+            collateral_usd: math::checked_sub(position.collateral_usd, released_collateral_usd)?
+                .saturating_sub(shortfall_usd),
+            unsettled_fees_usd: 0,
+            cumulative_lock_fee_snapshot: collateral_custody.get_cumulative_lock_fee(curtime)?,
+            ..position.clone()
+        }
+    };
+
+    Ok(ClosePositionQuote {
+        exit_price,
+        size_delta_usd,
+        profit_usd,
+        loss_usd,
+        close_fee_usd,
+        lock_fee_usd,
+        unsettled_fees_usd: position.unsettled_fees_usd,
+        returned_usd,
+        returned_amount,
+        residual_position,
+    })
+}
+
+/// Returns the largest collateral withdrawal, in USD and collateral tokens, that keeps the
+/// position leverage within `pricing.max_leverage` and its collateral above
+/// `pricing.min_collateral_usd`.
+pub fn get_max_withdrawable_collateral(
+    context: &PositionContext,
+    position: &Position,
+    target_price: &ResolvedPrice,
+    collateral_price: &ResolvedPrice,
+    curtime: i64,
+) -> Result<(u64, u64)> {
+    require!(
+        context.pool.permissions.allow_collateral_withdrawal
+            && context.collateral_custody.permissions.allow_collateral_withdrawal
+            && context.market.permissions.allow_collateral_withdrawal,
+        CompError::InstructionNotAllowed
+    );
+
+    let exit_price = context.get_exit_price(position, &target_price.min_price, &target_price.max_price)?;
+    let health = context.get_position_health(position, &exit_price, &collateral_price.min_price, curtime)?;
+
+    let max_collateral_usd = std::cmp::min(
+        health.equity_usd.saturating_sub(health.maintenance_margin_usd),
+        position
+            .collateral_usd
+            .saturating_sub(context.target_custody.pricing.min_collateral_usd),
+    );
+    let max_collateral_amount = std::cmp::min(
+        collateral_price
+            .max_price
+            .get_token_amount(max_collateral_usd, context.collateral_custody.decimals)?,
+        position.collateral_amount,
+    );
+
+    Ok((max_collateral_usd, max_collateral_amount))
+}
+
+/// Simulates depositing `collateral_amount` tokens of the collateral custody to `position`,
+/// valued at the collateral min price.
+pub fn simulate_add_collateral(
+    context: &PositionContext,
+    position: &Position,
+    target_price: &ResolvedPrice,
+    collateral_price: &ResolvedPrice,
+    collateral_amount: u64,
+    curtime: i64,
+) -> Result<CollateralQuote> {
+    let collateral_usd = collateral_price
+        .min_price
+        .get_asset_amount_usd(collateral_amount, context.collateral_custody.decimals)?;
+    let new_position = Position {
+        collateral_amount: math::checked_add(position.collateral_amount, collateral_amount)?,
+        collateral_usd: math::checked_add(position.collateral_usd, collateral_usd)?,
+        ..position.clone()
+    };
+    get_collateral_quote(
+        context,
+        &new_position,
+        target_price,
+        collateral_price,
+        collateral_amount,
+        collateral_usd,
+        curtime,
+    )
+}
+
+/// Simulates withdrawing `collateral_usd` of collateral from `position`, failing if it
+/// exceeds `get_max_withdrawable_collateral`.
+pub fn simulate_remove_collateral(
+    context: &PositionContext,
+    position: &Position,
+    target_price: &ResolvedPrice,
+    collateral_price: &ResolvedPrice,
+    collateral_usd: u64,
+    curtime: i64,
+) -> Result<CollateralQuote> {
+    let (max_collateral_usd, max_collateral_amount) =
+        get_max_withdrawable_collateral(context, position, target_price, collateral_price, curtime)?;
+    require!(
+        collateral_usd <= max_collateral_usd,
+        CompError::InsufficientCollateral
+    );
+
+    let collateral_amount = std::cmp::min(
+        collateral_price
+            .max_price
+            .get_token_amount(collateral_usd, context.collateral_custody.decimals)?,
+        max_collateral_amount,
+    );
+    let new_position = Position {
+        collateral_amount: math::checked_sub(position.collateral_amount, collateral_amount)?,
+        collateral_usd: math::checked_sub(position.collateral_usd, collateral_usd)?,
+        ..position.clone()
+    };
+    get_collateral_quote(
+        context,
+        &new_position,
+        target_price,
+        collateral_price,
+        collateral_amount,
+        collateral_usd,
+        curtime,
+    )
+}
+
+fn get_collateral_quote(
+    context: &PositionContext,
+    new_position: &Position,
+    target_price: &ResolvedPrice,
+    collateral_price: &ResolvedPrice,
+    collateral_amount: u64,
+    collateral_usd: u64,
+    curtime: i64,
+) -> Result<CollateralQuote> {
+    let exit_price = context.get_exit_price(new_position, &target_price.min_price, &target_price.max_price)?;
+    let health = context.get_position_health(new_position, &exit_price, &collateral_price.min_price, curtime)?;
+    let liquidation_price = liquidation::get_liquidation_prices(
+        context,
+        new_position,
+        &target_price.min_price,
+        &collateral_price.min_price,
+        curtime,
+    )?
+    .liquidation_price;
+
+    Ok(CollateralQuote {
+        collateral_amount,
+        collateral_usd,
+        position_collateral_usd: new_position.collateral_usd,
+        leverage: health.leverage,
+        liquidation_price,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::oracle::OracleSource};
+
+    struct Fixture {
+        pool: Pool,
+        market: Market,
+        target_custody: Custody,
+        collateral_custody: Custody,
+    }
+
+    impl Fixture {
+        // SOL long market with USDC collateral, 0.1% open and close fees, 0.2% volatility fee,
+        // 1.1x to 50x initial leverage, 100x max leverage and 10 USD of min collateral
+        fn new() -> Self {
+            let permissions = Permissions {
+                allow_open_position: true,
+                allow_close_position: true,
+                allow_collateral_withdrawal: true,
+                allow_size_change: true,
+                ..Permissions::default()
+            };
+            Self {
+                pool: Pool {
+                    permissions,
+                    ..Pool::default()
+                },
+                market: Market {
+                    side: Side::Long,
+                    max_payoff_bps: 10_000,
+                    permissions: MarketPermissions {
+                        allow_open_position: true,
+                        allow_close_position: true,
+                        allow_collateral_withdrawal: true,
+                        allow_size_change: true,
+                    },
+                    ..Market::default()
+                },
+                target_custody: Custody {
+                    decimals: 9,
+                    permissions,
+                    fees: Fees {
+                        open_position: 1_000_000,
+                        close_position: 1_000_000,
+                        volatility: 2_000_000,
+                        ..Fees::default()
+                    },
+                    pricing: PricingParams {
+                        min_initial_leverage: 11_000,
+                        max_initial_leverage: 500_000,
+                        max_leverage: 1_000_000,
+                        min_collateral_usd: 10_000_000,
+                        max_position_locked_usd: 100_000_000_000,
+                        ..PricingParams::default()
+                    },
+                    ..Custody::default()
+                },
+                collateral_custody: Custody {
+                    decimals: 6,
+                    permissions,
+                    assets: Assets {
+                        owned: 1_000_000_000_000,
+                        ..Assets::default()
+                    },
+                    ..Custody::default()
+                },
+            }
+        }
+
+        fn context(&self) -> PositionContext<'_> {
+            PositionContext {
+                pool: &self.pool,
+                market: &self.market,
+                target_custody: &self.target_custody,
+                collateral_custody: &self.collateral_custody,
+            }
+        }
+    }
+
+    fn get_price(price: u64, volatility_flag: bool) -> ResolvedPrice {
+        ResolvedPrice {
+            min_price: OraclePrice::new(price, -6),
+            max_price: OraclePrice::new(price, -6),
+            volatility_flag,
+            source: OracleSource::External,
+            is_backup: false,
+        }
+    }
+
+    // SOL at 100 USD and USDC at 1 USD
+    fn get_prices(volatility_flag: bool) -> (ResolvedPrice, ResolvedPrice) {
+        (get_price(100_000_000, volatility_flag), get_price(1_000_000, false))
+    }
+
+    #[test]
+    fn test_simulate_open_position() {
+        let fixture = Fixture::new();
+
+        // 1000 USD of SOL with 100 USDC, less the 1 USD open fee
+        let (target_price, collateral_price) = get_prices(false);
+        let quote = simulate_open_position(
+            &fixture.context(),
+            &target_price,
+            &collateral_price,
+            1_000_000_000,
+            100_000_000,
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            quote,
+            OpenPositionQuote {
+                prices_and_fee: NewPositionPricesAndFee {
+                    entry_price: OraclePrice::new(100_000_000, -6),
+                    entry_fee_amount: 1_000_000,
+                    vb_fee_amount: 0,
+                },
+                size_amount: 10_000_000_000,
+                collateral_usd: 99_000_000,
+                leverage: 101_010,
+                locked_amount: 1_000_000_000,
+                locked_usd: 1_000_000_000,
+                // 99 USD of collateral less the 1 USD close fee and 10 USD maintenance margin
+                liquidation_price: OraclePrice::new(91_200_000, -6),
+            }
+        );
+
+        // The volatility fee adds 2 USD while the target oracle is flagged
+        let (target_price, collateral_price) = get_prices(true);
+        let quote = simulate_open_position(
+            &fixture.context(),
+            &target_price,
+            &collateral_price,
+            1_000_000_000,
+            100_000_000,
+            0,
+        )
+        .unwrap();
+        assert_eq!(quote.prices_and_fee.entry_fee_amount, 1_000_000);
+        assert_eq!(quote.prices_and_fee.vb_fee_amount, 2_000_000);
+        assert_eq!(quote.collateral_usd, 97_000_000);
+        assert_eq!(quote.leverage, 103_092);
+        assert_eq!(quote.liquidation_price, OraclePrice::new(91_400_000, -6));
+    }
+
+    #[test]
+    fn test_simulate_open_position_errors() {
+        let fixture = Fixture::new();
+        let mut closed_market = Fixture::new();
+        closed_market.market.permissions.allow_open_position = false;
+        let mut low_position_limit = Fixture::new();
+        low_position_limit.target_custody.pricing.max_position_locked_usd = 500_000_000;
+        let mut locked_collateral = Fixture::new();
+        locked_collateral.collateral_custody.assets.locked = 1_000_000_000_000 - 999_999_999;
+
+        let cases = [
+            // (name, fixture, size_usd, collateral amount, expected error)
+            ("collateral covering the fees only", &fixture, 1_000_000_000, 1_000_000, CompError::InsufficientCollateral),
+            ("collateral below the minimum", &fixture, 100_000_000, 10_000_000, CompError::InsufficientCollateral),
+            ("leverage below the minimum", &fixture, 100_000_000, 100_000_000, CompError::MinLeverage),
+            ("leverage above the maximum", &fixture, 10_000_000_000, 100_000_000, CompError::MaxLeverage),
+            ("locked amount above the limit", &low_position_limit, 1_000_000_000, 100_000_000, CompError::MaxPositionLocked),
+            ("locked amount above the liquidity", &locked_collateral, 1_000_000_000, 100_000_000, CompError::InsufficientLiquidity),
+            ("open not allowed", &closed_market, 1_000_000_000, 100_000_000, CompError::InstructionNotAllowed),
+        ];
+        let (target_price, collateral_price) = get_prices(false);
+        for (name, fixture, size_usd, collateral_amount, expected) in cases.iter() {
+            assert_eq!(
+                simulate_open_position(
+                    &fixture.context(),
+                    &target_price,
+                    &collateral_price,
+                    *size_usd,
+                    *collateral_amount,
+                    0,
+                )
+                .unwrap_err(),
+                (*expected).into(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    // 10 SOL long entered at 100 USD with 100 USDC of collateral and 1000 USDC locked
+    fn get_position() -> Position {
+        Position {
+            entry_price: OraclePrice::new(100_000_000, -6),
+            size_amount: 10_000_000_000,
+            size_usd: 1_000_000_000,
+            size_decimals: 9,
+            locked_amount: 1_000_000_000,
+            locked_usd: 1_000_000_000,
+            locked_decimals: 6,
+            collateral_amount: 100_000_000,
+            collateral_usd: 100_000_000,
+            collateral_decimals: 6,
+            ..Position::default()
+        }
+    }
+
+    #[test]
+    fn test_simulate_close_position() {
+        let fixture = Fixture::new();
+        let position = get_position();
+        let collateral_price = get_price(1_000_000, false);
+
+        // Closing the whole position at 110 USD returns the collateral and 100 USD of profit,
+        // less the 1 USD close fee. Deltas above the size close the whole position.
+        for size_delta_usd in [1_000_000_000, 5_000_000_000] {
+            let quote = simulate_close_position(
+                &fixture.context(),
+                &position,
+                &get_price(110_000_000, false),
+                &collateral_price,
+                size_delta_usd,
+                0,
+            )
+            .unwrap();
+            assert_eq!(quote.size_delta_usd, 1_000_000_000);
+            assert_eq!(quote.profit_usd, 100_000_000);
+            assert_eq!(quote.close_fee_usd, 1_000_000);
+            assert_eq!(quote.returned_usd, 199_000_000);
+            assert_eq!(quote.returned_amount, 199_000_000);
+            assert_eq!(quote.residual_position, Position::default());
+        }
+
+        // Closing a quarter releases a quarter of the collateral and profit, less a 0.25 USD fee
+        let quote = simulate_close_position(
+            &fixture.context(),
+            &position,
+            &get_price(110_000_000, false),
+            &collateral_price,
+            250_000_000,
+            0,
+        )
+        .unwrap();
+        assert_eq!(quote.profit_usd, 25_000_000);
+        assert_eq!(quote.close_fee_usd, 250_000);
+        assert_eq!(quote.returned_usd, 49_750_000);
+        assert_eq!(
+            quote.residual_position,
+            Position {
+                size_amount: 7_500_000_000,
+                size_usd: 750_000_000,
+                locked_amount: 750_000_000,
+                locked_usd: 750_000_000,
+                collateral_amount: 75_000_000,
+                collateral_usd: 75_000_000,
+                ..position.clone()
+            }
+        );
+    }
+
+    #[test]
+    fn test_simulate_close_position_shortfall() {
+        let fixture = Fixture::new();
+        let position = Position {
+            unsettled_fees_usd: 30_000_000,
+            ..get_position()
+        };
+
+        // Closing a quarter at 90 USD loses 25 USD and owes 30.25 USD of fees against the
+        // 25 USD of released collateral: the missing 30.25 USD comes out of the residual
+        let quote = simulate_close_position(
+            &fixture.context(),
+            &position,
+            &get_price(90_000_000, false),
+            &get_price(1_000_000, false),
+            250_000_000,
+            0,
+        )
+        .unwrap();
+        assert_eq!(quote.loss_usd, 25_000_000);
+        assert_eq!(quote.returned_usd, 0);
+        assert_eq!(quote.returned_amount, 0);
+        assert_eq!(quote.residual_position.collateral_usd, 44_750_000);
+        assert_eq!(quote.residual_position.collateral_amount, 44_750_000);
+        assert_eq!(quote.residual_position.unsettled_fees_usd, 0);
+    }
+
+    #[test]
+    fn test_simulate_close_position_permissions() {
+        let position = get_position();
+        let (target_price, collateral_price) = get_prices(false);
+        let close = |fixture: &Fixture, size_delta_usd: u64| {
+            simulate_close_position(
+                &fixture.context(),
+                &position,
+                &target_price,
+                &collateral_price,
+                size_delta_usd,
+                0,
+            )
+        };
+
+        // Full closes need allow_close_position, partial ones allow_size_change
+        let mut no_size_change = Fixture::new();
+        no_size_change.market.permissions.allow_size_change = false;
+        assert!(close(&no_size_change, 1_000_000_000).is_ok());
+        assert_eq!(
+            close(&no_size_change, 250_000_000).unwrap_err(),
+            CompError::InstructionNotAllowed.into()
+        );
+
+        let mut no_close = Fixture::new();
+        no_close.pool.permissions.allow_close_position = false;
+        assert!(close(&no_close, 250_000_000).is_ok());
+        assert_eq!(
+            close(&no_close, 1_000_000_000).unwrap_err(),
+            CompError::InstructionNotAllowed.into()
+        );
+        assert_eq!(
+            close(&no_close, 5_000_000_000).unwrap_err(),
+            CompError::InstructionNotAllowed.into()
+        );
+
+        assert_eq!(
+            close(&Fixture::new(), 0).unwrap_err(),
+            CompError::InvalidSizeDelta.into()
+        );
+    }
+
+    #[test]
+    fn test_simulate_remove_collateral() {
+        let position = get_position();
+        let (target_price, collateral_price) = get_prices(false);
+        let mut high_min_collateral = Fixture::new();
+        high_min_collateral.target_custody.pricing.min_collateral_usd = 20_000_000;
+
+        // At entry the equity is 99 USD net of the close fee against 10 USD of maintenance
+        // margin, so the withdrawal is capped by the leverage unless the min collateral binds
+        for (fixture, expected_max_usd) in [(Fixture::new(), 89_000_000), (high_min_collateral, 80_000_000)] {
+            let context = fixture.context();
+            let (max_collateral_usd, max_collateral_amount) =
+                get_max_withdrawable_collateral(&context, &position, &target_price, &collateral_price, 0).unwrap();
+            assert_eq!((max_collateral_usd, max_collateral_amount), (expected_max_usd, expected_max_usd));
+
+            let quote =
+                simulate_remove_collateral(&context, &position, &target_price, &collateral_price, max_collateral_usd, 0)
+                    .unwrap();
+            assert_eq!(quote.collateral_amount, max_collateral_amount);
+            assert!(quote.leverage <= fixture.target_custody.pricing.max_leverage);
+            assert!(quote.position_collateral_usd >= fixture.target_custody.pricing.min_collateral_usd);
+
+            assert_eq!(
+                simulate_remove_collateral(
+                    &context,
+                    &position,
+                    &target_price,
+                    &collateral_price,
+                    max_collateral_usd + 1,
+                    0,
+                )
+                .unwrap_err(),
+                CompError::InsufficientCollateral.into()
+            );
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Decoding of raw account dumps into flash-read states.
+
+use {
+    crate::states::*,
+    anchor_lang::{prelude::*, Discriminator},
+    std::{collections::HashMap, fs, path::Path, str::FromStr},
+};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SnapshotError {
+    // Account data doesn't start with the discriminator of a supported state
+    UnknownAccount,
+    // Account data is too short for the state its discriminator announces
+    Truncated,
+    // Account data is long enough but holds invalid values, e.g. a bad enum tag
+    Malformed,
+}
+
+// Zeros appended to undecodable accounts to tell truncated data from malformed data
+const MAX_PADDING: usize = 10_240;
+
+#[derive(Default, Debug)]
+pub struct AccountSnapshot {
+    pub perpetuals: HashMap<Pubkey, Perpetuals>,
+    pub pools: HashMap<Pubkey, Pool>,
+    pub custodies: HashMap<Pubkey, Custody>,
+    pub markets: HashMap<Pubkey, Market>,
+    pub positions: HashMap<Pubkey, Position>,
+    pub custom_oracles: HashMap<Pubkey, CustomOracle>,
+    // Accounts that could not be decoded
+    pub rejected: Vec<(Pubkey, SnapshotError)>,
+}
+
+impl AccountSnapshot {
+    pub fn from_accounts<I, D>(accounts: I) -> Self
+    where
+        I: IntoIterator<Item = (Pubkey, D)>,
+        D: AsRef<[u8]>,
+    {
+        let mut snapshot = Self::default();
+        for (key, data) in accounts {
+            snapshot.insert(key, data.as_ref());
+        }
+        snapshot
+    }
+
+    /// Loads every file of `dir` named after an account address (optionally with an
+    /// extension, e.g. `<pubkey>.bin`) and holding the raw account data.
+    /// Files with any other name are skipped.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> std::io::Result<Self> {
+        let mut snapshot = Self::default();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let key = match path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| Pubkey::from_str(stem).ok())
+            {
+                Some(key) => key,
+                None => continue,
+            };
+            snapshot.insert(key, &fs::read(&path)?);
+        }
+        Ok(snapshot)
+    }
+
+    // Decodes a single account, recording it in `rejected` if it can't be classified
+    pub fn insert(&mut self, key: Pubkey, data: &[u8]) {
+        if let Err(err) = self.try_insert(key, data) {
+            self.rejected.push((key, err));
+        }
+    }
+
+    fn try_insert(&mut self, key: Pubkey, data: &[u8]) -> std::result::Result<(), SnapshotError> {
+        if data.len() < 8 {
+            return Err(SnapshotError::UnknownAccount);
+        }
+        let discriminator: [u8; 8] = data[..8].try_into().unwrap();
+
+        if discriminator == Perpetuals::DISCRIMINATOR {
+            self.perpetuals.insert(key, Self::decode(data)?);
+        } else if discriminator == Pool::DISCRIMINATOR {
+            self.pools.insert(key, Self::decode(data)?);
+        } else if discriminator == Custody::DISCRIMINATOR {
+            self.custodies.insert(key, Self::decode(data)?);
+        } else if discriminator == Market::DISCRIMINATOR {
+            self.markets.insert(key, Self::decode(data)?);
+        } else if discriminator == Position::DISCRIMINATOR {
+            self.positions.insert(key, Self::decode(data)?);
+        } else if discriminator == CustomOracle::DISCRIMINATOR {
+            self.custom_oracles.insert(key, Self::decode(data)?);
+        } else {
+            return Err(SnapshotError::UnknownAccount);
+        }
+        Ok(())
+    }
+
+    fn decode<T: AccountDeserialize>(data: &[u8]) -> std::result::Result<T, SnapshotError> {
+        T::try_deserialize(&mut &data[..]).map_err(|_| {
+            // Truncated data decodes once completed with zeros, malformed data doesn't
+            let mut padded = data.to_vec();
+            padded.resize(data.len() + MAX_PADDING, 0);
+            if T::try_deserialize(&mut &padded[..]).is_ok() {
+                SnapshotError::Truncated
+            } else {
+                SnapshotError::Malformed
+            }
+        })
+    }
+
+    // Returns the pool's custodies in `pool.custodies` order, if all of them were loaded
+    pub fn get_pool_custodies(&self, pool: &Pool) -> Option<Vec<Custody>> {
+        pool.custodies
+            .iter()
+            .map(|key| self.custodies.get(key).cloned())
+            .collect()
+    }
+
+    // Returns the pool's markets in `pool.markets` order, if all of them were loaded
+    pub fn get_pool_markets(&self, pool: &Pool) -> Option<Vec<Market>> {
+        pool.markets
+            .iter()
+            .map(|key| self.markets.get(key).cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_market_data() -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        Market::default().try_serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_insert() {
+        let data = get_market_data();
+        // Side tag follows the discriminator and three pubkeys
+        let mut bad_side = data.clone();
+        bad_side[8 + 3 * 32] = 9;
+        let (valid_key, truncated_key, malformed_key, unknown_key) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+
+        let snapshot = AccountSnapshot::from_accounts(vec![
+            (valid_key, data.clone()),
+            (truncated_key, data[..data.len() / 2].to_vec()),
+            (malformed_key, bad_side),
+            (unknown_key, vec![0; data.len()]),
+        ]);
+
+        assert_eq!(snapshot.markets.len(), 1);
+        assert!(snapshot.markets.contains_key(&valid_key));
+        let mut rejected = snapshot.rejected.clone();
+        rejected.sort_by_key(|(key, _)| *key);
+        let mut expected = vec![
+            (truncated_key, SnapshotError::Truncated),
+            (malformed_key, SnapshotError::Malformed),
+            (unknown_key, SnapshotError::UnknownAccount),
+        ];
+        expected.sort_by_key(|(key, _)| *key);
+        assert_eq!(rejected, expected);
+    }
+}
@@ -0,0 +1,81 @@
+//! Rewards estimation for staked LP tokens.
+
+use {
+    crate::{math, states::*},
+    anchor_lang::prelude::*,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct StakingRewardsEstimate {
+    // Rewards accrued since the staker's snapshot, in reward custody tokens
+    pub claimable_amount: u64,
+    // Staker's share of the daily fees distributed to stakers
+    pub daily_rewards_usd: u64,
+    pub apr_bps: u64,
+}
+
+/// Estimates the rewards of `staked_amount` active LP tokens.
+///
+/// `reward_snapshot` is the `reward_per_lp_staked` of `reward_custody` when the staker last
+/// claimed (implied RATE_DECIMALS). Projections assume the pool keeps accruing
+/// `daily_fees_usd` in fees, of which `pool.staking_fee_share_bps` goes to the active stake.
+/// `lp_price_usd` is the staked LP token price, e.g. from `valuation::get_pool_token_prices`.
+pub fn estimate_staking_rewards(
+    pool: &Pool,
+    reward_custody: &Custody,
+    staked_amount: u64,
+    reward_snapshot: u64,
+    daily_fees_usd: u64,
+    lp_price_usd: u64,
+) -> Result<StakingRewardsEstimate> {
+    let reward_per_lp_staked = reward_custody
+        .fees_stats
+        .reward_per_lp_staked
+        .saturating_sub(reward_snapshot);
+    let claimable_amount = math::checked_as_u64(math::checked_div(
+        math::checked_mul(staked_amount as u128, reward_per_lp_staked as u128)?,
+        Perpetuals::RATE_POWER,
+    )?)?;
+
+    let total_active = pool.total_staked.active_amount;
+    let daily_rewards_usd = if total_active > 0 {
+        let daily_staking_fees_usd = math::checked_div(
+            math::checked_mul(daily_fees_usd as u128, pool.staking_fee_share_bps as u128)?,
+            Perpetuals::BPS_POWER,
+        )?;
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(
+                daily_staking_fees_usd,
+                std::cmp::min(staked_amount, total_active) as u128,
+            )?,
+            total_active as u128,
+        )?)?
+    } else {
+        0
+    };
+
+    let staked_usd = math::checked_decimal_mul(
+        staked_amount,
+        -(Perpetuals::LP_DECIMALS as i32),
+        lp_price_usd,
+        -(Perpetuals::USD_DECIMALS as i32),
+        -(Perpetuals::USD_DECIMALS as i32),
+    )?;
+    let apr_bps = if staked_usd > 0 {
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(
+                math::checked_mul(daily_rewards_usd as u128, 365)?,
+                Perpetuals::BPS_POWER,
+            )?,
+            staked_usd as u128,
+        )?)?
+    } else {
+        0
+    };
+
+    Ok(StakingRewardsEstimate {
+        claimable_amount,
+        daily_rewards_usd,
+        apr_bps,
+    })
+}
@@ -1,5 +1,6 @@
 use anchor_lang::prelude::*;
 use core::cmp::Ordering;
+use pyth_solana_receiver_sdk::price_update::{PriceUpdateV2, VerificationLevel};
 use crate::{error::CompError, math};
 
 const ORACLE_EXPONENT_SCALE: i32 = -9;
@@ -222,14 +223,13 @@ impl OraclePrice {
     }
 
     fn get_int_oracle_price(
-        custom_price_info: &AccountInfo,
+        custom_oracle: &CustomOracle,
     ) -> Result<(OraclePrice, OraclePrice, u64, i64)> {
-        let oracle_acc = Account::<CustomOracle>::try_from(custom_price_info)?;
         Ok((
-            OraclePrice::new(oracle_acc.price, oracle_acc.expo),
-            OraclePrice::new(oracle_acc.ema, oracle_acc.expo),
-            oracle_acc.conf,
-            oracle_acc.publish_time,
+            OraclePrice::new(custom_oracle.price, custom_oracle.expo),
+            OraclePrice::new(custom_oracle.ema, custom_oracle.expo),
+            custom_oracle.conf,
+            custom_oracle.publish_time,
         ))
     }
 
@@ -243,19 +243,119 @@ impl OraclePrice {
         OraclePrice,
         OraclePrice,
         bool,
+    )> {
+        let oracle_acc = Account::<CustomOracle>::try_from(int_oracle_account)?;
+        Self::fetch_from_custom_oracle(&oracle_acc, oracle_params, current_time, is_stable)
+    }
+
+    // Same as fetch_from_oracle for an already decoded CustomOracle account
+    pub fn fetch_from_custom_oracle(
+        custom_oracle: &CustomOracle,
+        oracle_params: &OracleParams, // from custody.oracle
+        current_time: i64,
+        is_stable: bool,
+    ) -> Result<(
+        OraclePrice,
+        OraclePrice,
+        bool,
     )> {
         let (
             oracle_price,
             oracle_ema_price,
             oracle_conf,
             oracle_timestamp,
-        ) = Self::get_int_oracle_price(int_oracle_account)?;
+        ) = Self::get_int_oracle_price(custom_oracle)?;
 
         let price_age_sec = current_time.saturating_sub(oracle_timestamp);
         if price_age_sec > oracle_params.max_price_age_sec as i64 {
             return err!(CompError::InvalidOraclePrice);
         }
 
+        Self::get_min_max_price(
+            oracle_price,
+            oracle_ema_price,
+            oracle_conf,
+            oracle_params,
+            is_stable,
+        )
+    }
+
+    fn get_ext_oracle_price(
+        price_update: &PriceUpdateV2,
+    ) -> Result<(OraclePrice, OraclePrice, u64, i64)> {
+        let message = &price_update.price_message;
+        if price_update.verification_level != VerificationLevel::Full
+            || message.price <= 0
+            || message.ema_price <= 0
+        {
+            return err!(CompError::InvalidOraclePrice);
+        }
+        Ok((
+            OraclePrice::new(message.price as u64, message.exponent),
+            OraclePrice::new(message.ema_price as u64, message.exponent),
+            message.conf,
+            message.publish_time,
+        ))
+    }
+
+    // Returns the Pyth price if fetch_from_pyth_oracle accepts it: the confidence is only
+    // checked once the price diverges from its reference by max_divergence_bps
+    pub fn get_pyth_price(
+        price_update: &PriceUpdateV2,
+        oracle_params: &OracleParams, // from custody.oracle
+        current_time: i64,
+        is_stable: bool,
+    ) -> Result<OraclePrice> {
+        Self::fetch_from_pyth_oracle(price_update, oracle_params, current_time, is_stable)?;
+        let (oracle_price, _, _, _) = Self::get_ext_oracle_price(price_update)?;
+        Ok(oracle_price)
+    }
+
+    // Pyth counterpart of fetch_from_oracle.
+    // Returns (min_oracle_price, max_oracle_price, volatility_flag)
+    pub fn fetch_from_pyth_oracle(
+        price_update: &PriceUpdateV2,
+        oracle_params: &OracleParams, // from custody.oracle
+        current_time: i64,
+        is_stable: bool,
+    ) -> Result<(
+        OraclePrice,
+        OraclePrice,
+        bool,
+    )> {
+        let (
+            oracle_price,
+            oracle_ema_price,
+            oracle_conf,
+            oracle_timestamp,
+        ) = Self::get_ext_oracle_price(price_update)?;
+
+        let price_age_sec = current_time.saturating_sub(oracle_timestamp);
+        if price_age_sec > oracle_params.max_price_age_sec as i64 {
+            return err!(CompError::InvalidOraclePrice);
+        }
+
+        Self::get_min_max_price(
+            oracle_price,
+            oracle_ema_price,
+            oracle_conf,
+            oracle_params,
+            is_stable,
+        )
+    }
+
+    // Widens the price by its confidence when it diverges from the reference price
+    fn get_min_max_price(
+        oracle_price: OraclePrice,
+        oracle_ema_price: OraclePrice,
+        oracle_conf: u64,
+        oracle_params: &OracleParams,
+        is_stable: bool,
+    ) -> Result<(
+        OraclePrice,
+        OraclePrice,
+        bool,
+    )> {
         let divergence_bps = if is_stable {
             let one_usd = OraclePrice::new(
                 math::checked_pow(10_u64, oracle_price.exponent.abs() as usize)?,
@@ -632,6 +732,91 @@ impl Custody {
         }
     }
 
+    // Returns locked / owned assets with implied RATE_DECIMALS
+    pub fn get_utilization(&self) -> Result<u64> {
+        if self.assets.owned == 0 {
+            return Ok(0);
+        }
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(self.assets.locked as u128, Perpetuals::RATE_POWER)?,
+            self.assets.owned as u128,
+        )?)
+    }
+
+    // Returns the hourly borrow rate implied by borrow_rate params at the current utilization.
+    // The rate grows by slope1 up to optimal_utilization and by slope2 beyond it.
+    pub fn get_borrow_rate(&self) -> Result<u64> {
+        if self.is_virtual {
+            return Ok(0);
+        }
+        let params = &self.borrow_rate;
+        let utilization = self.get_utilization()?;
+
+        if utilization < params.optimal_utilization {
+            math::checked_add(
+                params.base_rate,
+                math::checked_as_u64(math::checked_div(
+                    math::checked_mul(params.slope1 as u128, utilization as u128)?,
+                    params.optimal_utilization as u128,
+                )?)?,
+            )
+        } else {
+            let base_rate = math::checked_add(params.base_rate, params.slope1)?;
+            if params.optimal_utilization as u128 >= Perpetuals::RATE_POWER {
+                return Ok(base_rate);
+            }
+            math::checked_add(
+                base_rate,
+                math::checked_as_u64(math::checked_div(
+                    math::checked_mul(
+                        params.slope2 as u128,
+                        math::checked_sub(utilization, params.optimal_utilization)? as u128,
+                    )?,
+                    math::checked_sub(Perpetuals::RATE_POWER, params.optimal_utilization as u128)?,
+                )?)?,
+            )
+        }
+    }
+
+    // Projects the cumulative lock fee at future_time, assuming the borrow rate moves to
+    // the one implied by the current utilization at curtime and stays there
+    pub fn get_projected_cumulative_lock_fee(&self, curtime: i64, future_time: i64) -> Result<u128> {
+        let cumulative_lock_fee = self.get_cumulative_lock_fee(curtime)?;
+        if future_time <= curtime {
+            return Ok(cumulative_lock_fee);
+        }
+        math::checked_add(
+            cumulative_lock_fee,
+            math::checked_ceil_div(
+                math::checked_mul(
+                    math::checked_sub(future_time, curtime)? as u128,
+                    self.get_borrow_rate()? as u128,
+                )?,
+                3600,
+            )?,
+        )
+    }
+
+    // Projects the lock fee owed by the position at future_time
+    pub fn get_projected_lock_fee_usd(&self, position: &Position, curtime: i64, future_time: i64) -> Result<u64> {
+        if position.locked_usd == 0 || self.is_virtual {
+            return Ok(0);
+        }
+
+        let cumulative_lock_fee = self.get_projected_cumulative_lock_fee(curtime, future_time)?;
+        if cumulative_lock_fee <= position.cumulative_lock_fee_snapshot {
+            return Ok(0);
+        }
+
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(
+                math::checked_sub(cumulative_lock_fee, position.cumulative_lock_fee_snapshot)?,
+                position.locked_usd as u128,
+            )?,
+            Perpetuals::RATE_POWER,
+        )?)
+    }
+
     pub fn get_trade_spread(
         &self,
         size_usd: u64,
@@ -753,7 +938,7 @@ impl Market {
 }
 
 #[account]
-#[derive(Default, Debug)]
+#[derive(Default, Debug, PartialEq)]
 pub struct Position {
     pub owner: Pubkey,
     pub market: Pubkey,
@@ -778,6 +963,48 @@ pub struct Position {
     pub bump: u8,
 }
 
+impl Position {
+    // Returns (profit_usd, loss_usd) of the position if closed at exit_price.
+    // Profit is capped by max_payoff_bps of the locked amount, loss by the collateral.
+    pub fn get_pnl_usd(
+        &self,
+        exit_price: &OraclePrice,
+        side: Side,
+        collateral_price: &OraclePrice, // price of the collateral custody token
+        max_payoff_bps: u64, // from: market.max_payoff_bps
+    ) -> Result<(u64, u64)> {
+        if self.size_amount == 0 {
+            return Ok((0, 0));
+        }
+
+        let exit_price = &exit_price.scale_to_exponent(self.entry_price.exponent)?;
+        let in_profit = if side == Side::Short {
+            *exit_price < self.entry_price
+        } else {
+            *exit_price > self.entry_price
+        };
+        let price_diff = if *exit_price > self.entry_price {
+            exit_price.checked_sub(&self.entry_price)?
+        } else {
+            self.entry_price.checked_sub(exit_price)?
+        };
+        let pnl_usd = price_diff.get_asset_amount_usd(self.size_amount, self.size_decimals)?;
+
+        if in_profit {
+            let max_profit_usd = math::checked_as_u64(math::checked_div(
+                math::checked_mul(
+                    collateral_price.get_asset_amount_usd(self.locked_amount, self.locked_decimals)? as u128,
+                    max_payoff_bps as u128,
+                )?,
+                Perpetuals::BPS_POWER,
+            )?)?;
+            Ok((std::cmp::min(pnl_usd, max_profit_usd), 0))
+        } else {
+            Ok((0, std::cmp::min(pnl_usd, self.collateral_usd)))
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
 pub struct StakeStats {
     pub pending_activation: u64,
@@ -792,3 +1019,235 @@ pub struct NewPositionPricesAndFee {
     pub entry_fee_amount: u64,
     pub vb_fee_amount: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, pythnet_sdk::messages::PriceFeedMessage};
+
+    // 5 SOL long entered at 100 USD (exponent -6) with 100 USD collateral and 5 SOL locked
+    fn get_position() -> Position {
+        Position {
+            entry_price: OraclePrice::new(100_000_000, -6),
+            size_amount: 5_000_000_000,
+            size_usd: 500_000_000,
+            size_decimals: 9,
+            locked_amount: 5_000_000_000,
+            locked_decimals: 9,
+            collateral_usd: 100_000_000,
+            ..Position::default()
+        }
+    }
+
+    #[test]
+    fn test_get_pnl_usd() {
+        let position = get_position();
+        let collateral_price = OraclePrice::new(100_000_000, -6);
+        let cases = [
+            // (name, side, exit price, max payoff bps, expected (profit, loss))
+            ("long profit", Side::Long, OraclePrice::new(110_000_000, -6), 10_000, (50_000_000, 0)),
+            ("long profit capped", Side::Long, OraclePrice::new(110_000_000, -6), 500, (25_000_000, 0)),
+            ("long loss", Side::Long, OraclePrice::new(90_000_000, -6), 10_000, (0, 50_000_000)),
+            ("long loss capped", Side::Long, OraclePrice::new(70_000_000, -6), 10_000, (0, 100_000_000)),
+            ("short profit", Side::Short, OraclePrice::new(90_000_000, -6), 10_000, (50_000_000, 0)),
+            ("short loss capped", Side::Short, OraclePrice::new(130_000_000, -6), 10_000, (0, 100_000_000)),
+            ("long at entry", Side::Long, OraclePrice::new(100_000_000, -6), 10_000, (0, 0)),
+            ("long profit exponent -8", Side::Long, OraclePrice::new(11_000_000_000, -8), 10_000, (50_000_000, 0)),
+            ("short loss exponent -8", Side::Short, OraclePrice::new(10_400_000_000, -8), 10_000, (0, 20_000_000)),
+        ];
+
+        for (name, side, exit_price, max_payoff_bps, expected) in cases.iter() {
+            assert_eq!(
+                position.get_pnl_usd(exit_price, *side, &collateral_price, *max_payoff_bps).unwrap(),
+                *expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_pnl_usd_zero_size() {
+        let position = Position {
+            size_amount: 0,
+            ..get_position()
+        };
+        assert_eq!(
+            position
+                .get_pnl_usd(&OraclePrice::new(200, 0), Side::Long, &OraclePrice::new(100, 0), 10_000)
+                .unwrap(),
+            (0, 0)
+        );
+    }
+
+    // 1e-5 hourly base rate, +1e-4 up to the optimal utilization and +1e-3 beyond it
+    fn get_custody(optimal_utilization: u64, owned: u64, locked: u64) -> Custody {
+        Custody {
+            borrow_rate: BorrowRateParams {
+                base_rate: 10_000,
+                slope1: 100_000,
+                slope2: 1_000_000,
+                optimal_utilization,
+            },
+            borrow_rate_state: BorrowRateState {
+                current_rate: 36_000,
+                cumulative_lock_fee: 1_000_000,
+                last_update: 0,
+            },
+            assets: Assets {
+                owned,
+                locked,
+                ..Assets::default()
+            },
+            ..Custody::default()
+        }
+    }
+
+    #[test]
+    fn test_get_borrow_rate() {
+        let cases = [
+            // (name, optimal utilization, locked out of 1000 owned, expected rate)
+            ("empty", 800_000_000, 0, 10_000),
+            ("below optimal", 800_000_000, 400, 60_000),
+            ("at optimal", 800_000_000, 800, 110_000),
+            ("above optimal", 800_000_000, 900, 610_000),
+            ("fully utilized", 800_000_000, 1000, 1_110_000),
+            ("zero optimal", 0, 500, 610_000),
+            ("zero optimal and utilization", 0, 0, 110_000),
+            ("optimal at 100% below", 1_000_000_000, 500, 60_000),
+            ("optimal at 100% reached", 1_000_000_000, 1000, 110_000),
+            ("optimal above 100%", 2_000_000_000, 1000, 60_000),
+        ];
+        for (name, optimal_utilization, locked, expected) in cases.iter() {
+            let custody = get_custody(*optimal_utilization, 1000, *locked);
+            assert_eq!(custody.get_borrow_rate().unwrap(), *expected, "{}", name);
+        }
+
+        let custody = Custody {
+            is_virtual: true,
+            ..get_custody(800_000_000, 1000, 900)
+        };
+        assert_eq!(custody.get_borrow_rate().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_get_projected_lock_fee_usd() {
+        // 90% utilization projects a 610_000 hourly rate
+        let custody = get_custody(800_000_000, 1000, 900);
+        let position = Position {
+            locked_usd: 1_000_000_000,
+            cumulative_lock_fee_snapshot: 1_000_000,
+            ..get_position()
+        };
+
+        // One hour at the current rate is accrued by curtime
+        assert_eq!(custody.get_cumulative_lock_fee(3600).unwrap(), 1_036_000);
+        assert_eq!(custody.get_projected_cumulative_lock_fee(3600, 7200).unwrap(), 1_646_000);
+        assert_eq!(custody.get_projected_lock_fee_usd(&position, 3600, 7200).unwrap(), 646_000);
+
+        // Projections at or before curtime are the fee accrued so far
+        for future_time in [3600, 0] {
+            assert_eq!(custody.get_projected_cumulative_lock_fee(3600, future_time).unwrap(), 1_036_000);
+            assert_eq!(custody.get_projected_lock_fee_usd(&position, 3600, future_time).unwrap(), 36_000);
+        }
+        assert_eq!(
+            custody.get_projected_lock_fee_usd(&position, 3600, 3600).unwrap(),
+            custody.get_lock_fee_usd(&position, 3600).unwrap()
+        );
+
+        let custody = Custody {
+            is_virtual: true,
+            ..custody
+        };
+        assert_eq!(custody.get_projected_lock_fee_usd(&position, 3600, 7200).unwrap(), 0);
+    }
+
+    // 100 USD with a 0.1 USD confidence (10 bps), published at 100
+    fn get_price_update(price: i64, ema_price: i64, conf: u64) -> PriceUpdateV2 {
+        PriceUpdateV2 {
+            write_authority: Pubkey::default(),
+            verification_level: VerificationLevel::Full,
+            price_message: PriceFeedMessage {
+                feed_id: [0; 32],
+                price,
+                conf,
+                exponent: -6,
+                publish_time: 100,
+                prev_publish_time: 99,
+                ema_price,
+                ema_conf: conf,
+            },
+            posted_slot: 0,
+        }
+    }
+
+    // 1% divergence and 20 bps confidence allowed, 10 seconds max age
+    fn get_oracle_params(max_conf_bps: u64) -> OracleParams {
+        OracleParams {
+            max_divergence_bps: 100,
+            max_conf_bps,
+            max_price_age_sec: 10,
+            ..OracleParams::default()
+        }
+    }
+
+    #[test]
+    fn test_get_pyth_price() {
+        let price = OraclePrice::new(100_000_000, -6);
+        let params = get_oracle_params(20);
+        let update = get_price_update(100_000_000, 100_000_000, 100_000);
+        assert_eq!(OraclePrice::get_pyth_price(&update, &params, 110, false).unwrap(), price);
+
+        // The confidence is only checked once the price diverges from the EMA
+        let wide_update = get_price_update(100_000_000, 100_000_000, 1_000_000);
+        assert_eq!(OraclePrice::get_pyth_price(&wide_update, &params, 110, false).unwrap(), price);
+        assert_eq!(
+            OraclePrice::get_pyth_price(&wide_update, &get_oracle_params(0), 110, false).unwrap(),
+            price
+        );
+        let diverged_update = get_price_update(100_000_000, 98_000_000, 100_000);
+        assert_eq!(OraclePrice::get_pyth_price(&diverged_update, &params, 110, false).unwrap(), price);
+        assert_eq!(
+            OraclePrice::fetch_from_pyth_oracle(&diverged_update, &params, 110, false).unwrap(),
+            (OraclePrice::new(99_900_000, -6), OraclePrice::new(100_100_000, -6), true)
+        );
+
+        // Stable prices diverge from 1 USD rather than from the EMA
+        let stable_update = get_price_update(1_000_000, 1_000_000, 1_000);
+        assert_eq!(
+            OraclePrice::get_pyth_price(&stable_update, &params, 110, true).unwrap(),
+            OraclePrice::new(1_000_000, -6)
+        );
+    }
+
+    #[test]
+    fn test_get_pyth_price_rejections() {
+        let params = get_oracle_params(20);
+        let partial_update = PriceUpdateV2 {
+            verification_level: VerificationLevel::Partial { num_signatures: 5 },
+            ..get_price_update(100_000_000, 100_000_000, 100_000)
+        };
+        let cases = [
+            ("partially verified", partial_update, 110),
+            ("zero price", get_price_update(0, 100_000_000, 100_000), 110),
+            ("negative price", get_price_update(-100_000_000, 100_000_000, 100_000), 110),
+            ("negative ema price", get_price_update(100_000_000, -100_000_000, 100_000), 110),
+            ("stale", get_price_update(100_000_000, 100_000_000, 100_000), 111),
+            ("diverged with wide confidence", get_price_update(100_000_000, 98_000_000, 200_000), 110),
+        ];
+        for (name, update, current_time) in cases.iter() {
+            assert_eq!(
+                OraclePrice::get_pyth_price(update, &params, *current_time, false).unwrap_err(),
+                CompError::InvalidOraclePrice.into(),
+                "{}",
+                name
+            );
+        }
+
+        // A stable price 2% off its peg with a wide confidence
+        assert_eq!(
+            OraclePrice::get_pyth_price(&get_price_update(980_000, 1_000_000, 10_000), &params, 110, true)
+                .unwrap_err(),
+            CompError::InvalidOraclePrice.into()
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Swap quotes between two custodies of a pool.
+
+use {
+    crate::{error::CompError, fees, math, states::*},
+    anchor_lang::prelude::*,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct SwapQuote {
+    pub amount_out: u64,
+    // Fee charged on the input, in input tokens
+    pub fee_in: u64,
+    // Fee charged on the output, in output tokens
+    pub fee_out: u64,
+    // Fee rates applied, with implied RATE_DECIMALS
+    pub fee_in_rate: u64,
+    pub fee_out_rate: u64,
+    // Share of the pool AUM held by the input and output custodies after the swap,
+    // to be compared against pool.ratios (BPS_DECIMALS)
+    pub ratio_in: u64,
+    pub ratio_out: u64,
+}
+
+// Quotes a swap of amount_in tokens of custodies[token_id_in] into custodies[token_id_out].
+// custodies and prices follow pool.custodies order.
+pub fn get_swap_amount_and_fees(
+    pool: &Pool,
+    custodies: &[Custody],
+    prices: &[OraclePrice],
+    token_id_in: usize,
+    token_id_out: usize,
+    amount_in: u64,
+) -> Result<SwapQuote> {
+    if custodies.len() != pool.custodies.len()
+        || prices.len() != pool.custodies.len()
+        || pool.ratios.len() != pool.custodies.len()
+    {
+        return Err(ProgramError::NotEnoughAccountKeys.into());
+    }
+    require!(token_id_in != token_id_out, CompError::SameCustodySwap);
+
+    let custody_in = &custodies[token_id_in];
+    let custody_out = &custodies[token_id_out];
+    require!(
+        pool.permissions.allow_swap
+            && custody_in.permissions.allow_swap
+            && custody_out.permissions.allow_swap,
+        CompError::InstructionNotAllowed
+    );
+
+    let (fees_in, fees_out) = if custody_in.is_stable && custody_out.is_stable {
+        (custody_in.fees.stable_swap_in, custody_out.fees.stable_swap_out)
+    } else {
+        (custody_in.fees.swap_in, custody_out.fees.swap_out)
+    };
+
+    // The swap spread values the input lower and the output higher than the oracle
+    let price_in = apply_spread(&prices[token_id_in], custody_in.pricing.swap_spread, false)?;
+    let price_out = apply_spread(&prices[token_id_out], custody_out.pricing.swap_spread, true)?;
+
+    // Fee rates are determined by the ratios the gross swap would leave the pool in
+    let gross_amount_out = price_out.get_token_amount(
+        price_in.get_asset_amount_usd(amount_in, custody_in.decimals)?,
+        custody_out.decimals,
+    )?;
+    let (ratio_in, ratio_out) = get_swap_ratios(
+        custodies,
+        prices,
+        token_id_in,
+        token_id_out,
+        amount_in,
+        gross_amount_out,
+    )?;
+    let fee_in_rate = fees::get_ratio_fee(
+        custody_in.fees.mode,
+        &fees_in,
+        &pool.ratios[token_id_in],
+        ratio_in,
+        true,
+    )?;
+    let fee_out_rate = fees::get_ratio_fee(
+        custody_out.fees.mode,
+        &fees_out,
+        &pool.ratios[token_id_out],
+        ratio_out,
+        false,
+    )?;
+
+    let fee_in = pool.get_fee_amount(fee_in_rate, amount_in)?;
+    let amount_out_before_fee = price_out.get_token_amount(
+        price_in.get_asset_amount_usd(math::checked_sub(amount_in, fee_in)?, custody_in.decimals)?,
+        custody_out.decimals,
+    )?;
+    let fee_out = pool.get_fee_amount(fee_out_rate, amount_out_before_fee)?;
+    let amount_out = math::checked_sub(amount_out_before_fee, fee_out)?;
+
+    let available_amount = custody_out.assets.owned.saturating_sub(custody_out.assets.locked);
+    require!(amount_out <= available_amount, CompError::InsufficientLiquidity);
+
+    // Fees are retained by the pool
+    let (ratio_in, ratio_out) = get_swap_ratios(
+        custodies,
+        prices,
+        token_id_in,
+        token_id_out,
+        amount_in,
+        amount_out,
+    )?;
+
+    Ok(SwapQuote {
+        amount_out,
+        fee_in,
+        fee_out,
+        fee_in_rate,
+        fee_out_rate,
+        ratio_in,
+        ratio_out,
+    })
+}
+
+// Moves the price by spread (BPS_DECIMALS), up if increase is set and down otherwise
+fn apply_spread(price: &OraclePrice, spread: u64, increase: bool) -> Result<OraclePrice> {
+    let factor = if increase {
+        math::checked_add(Perpetuals::BPS_POWER, spread as u128)?
+    } else {
+        Perpetuals::BPS_POWER.saturating_sub(spread as u128)
+    };
+    Ok(OraclePrice::new(
+        math::checked_as_u64(math::checked_div(
+            math::checked_mul(price.price as u128, factor)?,
+            Perpetuals::BPS_POWER,
+        )?)?,
+        price.exponent,
+    ))
+}
+
+// Returns the (input, output) custody ratios once amount_in is added to the input custody
+// and amount_out removed from the output custody
+fn get_swap_ratios(
+    custodies: &[Custody],
+    prices: &[OraclePrice],
+    token_id_in: usize,
+    token_id_out: usize,
+    amount_in: u64,
+    amount_out: u64,
+) -> Result<(u64, u64)> {
+    let mut pool_aum_usd: u64 = 0;
+    let mut custody_in_aum_usd: u64 = 0;
+    let mut custody_out_aum_usd: u64 = 0;
+
+    for (idx, custody) in custodies.iter().enumerate() {
+        let owned = if idx == token_id_in {
+            math::checked_add(custody.assets.owned, amount_in)?
+        } else if idx == token_id_out {
+            custody.assets.owned.saturating_sub(amount_out)
+        } else {
+            custody.assets.owned
+        };
+        let aum_usd = prices[idx].get_asset_amount_usd(owned, custody.decimals)?;
+        if idx == token_id_in {
+            custody_in_aum_usd = aum_usd;
+        } else if idx == token_id_out {
+            custody_out_aum_usd = aum_usd;
+        }
+        pool_aum_usd = math::checked_add(pool_aum_usd, aum_usd)?;
+    }
+
+    Ok((
+        fees::get_token_ratio(custody_in_aum_usd, pool_aum_usd)?,
+        fees::get_token_ratio(custody_out_aum_usd, pool_aum_usd)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL: usize = 0;
+    const USDC: usize = 1;
+    const USDT: usize = 2;
+
+    // Only the target fee is charged in FeesMode::Fixed
+    fn get_fees(target_fee: u64) -> RatioFees {
+        RatioFees {
+            target_fee,
+            ..RatioFees::default()
+        }
+    }
+
+    fn get_custody(decimals: u8, is_stable: bool, owned: u64, swap_spread: u64) -> Custody {
+        Custody {
+            decimals,
+            is_stable,
+            fees: Fees {
+                mode: FeesMode::Fixed,
+                // 0.1% in and 0.2% out, 0.01% and 0.02% between stables
+                swap_in: get_fees(1_000_000),
+                swap_out: get_fees(2_000_000),
+                stable_swap_in: get_fees(100_000),
+                stable_swap_out: get_fees(200_000),
+                ..Fees::default()
+            },
+            pricing: PricingParams {
+                swap_spread,
+                ..PricingParams::default()
+            },
+            permissions: Permissions {
+                allow_swap: true,
+                ..Permissions::default()
+            },
+            assets: Assets {
+                owned,
+                ..Assets::default()
+            },
+            ..Custody::default()
+        }
+    }
+
+    // 100 SOL at 100 USD with a 1% swap spread, 10k USDC and 10k USDT: 30k USD AUM
+    fn get_fixtures() -> (Pool, Vec<Custody>, Vec<OraclePrice>) {
+        let pool = Pool {
+            permissions: Permissions {
+                allow_swap: true,
+                ..Permissions::default()
+            },
+            custodies: vec![Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()],
+            ratios: vec![TokenRatios::default(); 3],
+            ..Pool::default()
+        };
+        let custodies = vec![
+            get_custody(9, false, 100_000_000_000, 100),
+            get_custody(6, true, 10_000_000_000, 0),
+            get_custody(6, true, 10_000_000_000, 0),
+        ];
+        let prices = vec![
+            OraclePrice::new(100_000_000, -6),
+            OraclePrice::new(1_000_000, -6),
+            OraclePrice::new(1_000_000, -6),
+        ];
+        (pool, custodies, prices)
+    }
+
+    #[test]
+    fn test_swap_non_stable_in() {
+        let (pool, custodies, prices) = get_fixtures();
+        let quote = get_swap_amount_and_fees(&pool, &custodies, &prices, SOL, USDC, 1_000_000_000).unwrap();
+
+        // 1 SOL less the 0.1% fee is 0.999 SOL valued at 99 USD after the spread: 98.901 USDC,
+        // less the 0.2% fee rounded up. The pool ends with 101 SOL and 9,901.296802 USDC.
+        assert_eq!(
+            quote,
+            SwapQuote {
+                amount_out: 98_703_198,
+                fee_in: 1_000_000,
+                fee_out: 197_802,
+                fee_in_rate: 1_000_000,
+                fee_out_rate: 2_000_000,
+                // 10,100 / 30,001.296802 and 9,901.296802 / 30,001.296802
+                ratio_in: 3366,
+                ratio_out: 3300,
+            }
+        );
+    }
+
+    #[test]
+    fn test_swap_non_stable_out() {
+        let (pool, custodies, prices) = get_fixtures();
+        let quote = get_swap_amount_and_fees(&pool, &custodies, &prices, USDC, SOL, 100_000_000).unwrap();
+
+        // 100 USDC less the 0.1% fee buys 99.9 USD of SOL at 101 USD after the spread:
+        // 0.989108910 SOL, less the 0.2% fee rounded up
+        assert_eq!(
+            quote,
+            SwapQuote {
+                amount_out: 987_130_692,
+                fee_in: 100_000,
+                fee_out: 1_978_218,
+                fee_in_rate: 1_000_000,
+                fee_out_rate: 2_000_000,
+                // 10,100 / 30,001.286930 and 9,901.286930 / 30,001.286930
+                ratio_in: 3366,
+                ratio_out: 3300,
+            }
+        );
+    }
+
+    #[test]
+    fn test_swap_stable() {
+        let (pool, custodies, prices) = get_fixtures();
+        let quote = get_swap_amount_and_fees(&pool, &custodies, &prices, USDC, USDT, 100_000_000).unwrap();
+
+        // No spread, 0.01% in and 0.02% out
+        assert_eq!(
+            quote,
+            SwapQuote {
+                amount_out: 99_970_002,
+                fee_in: 10_000,
+                fee_out: 19_998,
+                fee_in_rate: 100_000,
+                fee_out_rate: 200_000,
+                // 10,100 / 30,000.029998 and 9,900.029998 / 30,000.029998
+                ratio_in: 3366,
+                ratio_out: 3300,
+            }
+        );
+    }
+
+    #[test]
+    fn test_swap_errors() {
+        let (pool, mut custodies, prices) = get_fixtures();
+        assert_eq!(
+            get_swap_amount_and_fees(&pool, &custodies, &prices, USDC, USDC, 100_000_000).unwrap_err(),
+            CompError::SameCustodySwap.into()
+        );
+
+        // 1 SOL buys ~98.7 USDC
+        custodies[USDC].assets.locked = custodies[USDC].assets.owned - 98_703_197;
+        assert_eq!(
+            get_swap_amount_and_fees(&pool, &custodies, &prices, SOL, USDC, 1_000_000_000).unwrap_err(),
+            CompError::InsufficientLiquidity.into()
+        );
+        custodies[USDC].assets.locked -= 1;
+        assert!(get_swap_amount_and_fees(&pool, &custodies, &prices, SOL, USDC, 1_000_000_000).is_ok());
+
+        custodies[USDT].permissions.allow_swap = false;
+        assert_eq!(
+            get_swap_amount_and_fees(&pool, &custodies, &prices, USDC, USDT, 100_000_000).unwrap_err(),
+            CompError::InstructionNotAllowed.into()
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Take profit and stop loss evaluation of positions.
+
+use {
+    crate::{error::CompError, position::PositionContext, states::*},
+    anchor_lang::prelude::*,
+};
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct TriggerStatus {
+    pub take_profit_triggered: bool,
+    pub stop_loss_triggered: bool,
+    // Amount returned to the owner if closed at the trigger price, net of close and lock fees
+    pub take_profit_payout_usd: u64,
+    pub stop_loss_payout_usd: u64,
+}
+
+// Trigger prices are unset while zero
+fn is_set(price: &OraclePrice) -> bool {
+    price.price > 0
+}
+
+pub fn is_take_profit_triggered(position: &Position, side: Side, exit_price: &OraclePrice) -> bool {
+    if !is_set(&position.take_profit_price) {
+        return false;
+    }
+    if side == Side::Long {
+        *exit_price >= position.take_profit_price
+    } else {
+        *exit_price <= position.take_profit_price
+    }
+}
+
+pub fn is_stop_loss_triggered(position: &Position, side: Side, exit_price: &OraclePrice) -> bool {
+    if !is_set(&position.stop_loss_price) {
+        return false;
+    }
+    if side == Side::Long {
+        *exit_price <= position.stop_loss_price
+    } else {
+        *exit_price >= position.stop_loss_price
+    }
+}
+
+/// Evaluates the trigger prices of `position` against `exit_price`, as given by
+/// `Pool::get_exit_price`.
+///
+/// Payouts are estimated at the trigger prices themselves and are zero for unset triggers.
+pub fn get_trigger_status(
+    context: &PositionContext,
+    position: &Position,
+    exit_price: &OraclePrice,
+    collateral_price: &OraclePrice,
+    curtime: i64,
+) -> Result<TriggerStatus> {
+    let side = context.market.side;
+
+    let take_profit_payout_usd = if is_set(&position.take_profit_price) {
+        context
+            .get_position_health(position, &position.take_profit_price, collateral_price, curtime)?
+            .equity_usd
+    } else {
+        0
+    };
+    let stop_loss_payout_usd = if is_set(&position.stop_loss_price) {
+        context
+            .get_position_health(position, &position.stop_loss_price, collateral_price, curtime)?
+            .equity_usd
+    } else {
+        0
+    };
+
+    Ok(TriggerStatus {
+        take_profit_triggered: is_take_profit_triggered(position, side, exit_price),
+        stop_loss_triggered: is_stop_loss_triggered(position, side, exit_price),
+        take_profit_payout_usd,
+        stop_loss_payout_usd,
+    })
+}
+
+/// Checks that the trigger prices of `position` are on the profitable (take profit) or the
+/// losing (stop loss) side of the entry price, and that the stop loss would be hit before
+/// `liquidation_price`, e.g. as returned by `liquidation::get_liquidation_prices`.
+pub fn validate_trigger_prices(
+    position: &Position,
+    side: Side,
+    liquidation_price: &OraclePrice,
+) -> Result<()> {
+    let entry_price = &position.entry_price;
+    let liquidation_set = is_set(liquidation_price);
+
+    if is_set(&position.take_profit_price) {
+        let valid = if side == Side::Long {
+            position.take_profit_price > *entry_price
+        } else {
+            position.take_profit_price < *entry_price
+        };
+        require!(valid, CompError::InvalidTakeProfitPrice);
+    }
+
+    if is_set(&position.stop_loss_price) {
+        let valid = if side == Side::Long {
+            position.stop_loss_price < *entry_price
+                && (!liquidation_set || position.stop_loss_price > *liquidation_price)
+        } else {
+            position.stop_loss_price > *entry_price
+                && (!liquidation_set || position.stop_loss_price < *liquidation_price)
+        };
+        require!(valid, CompError::InvalidStopLossPrice);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(price: u64) -> OraclePrice {
+        OraclePrice::new(price * 1_000_000, -6)
+    }
+
+    // 5 SOL entered at 100 USD with 100 USD of USDC collateral
+    fn get_position(take_profit_price: u64, stop_loss_price: u64) -> Position {
+        Position {
+            entry_price: price(100),
+            size_amount: 5_000_000_000,
+            size_usd: 500_000_000,
+            size_decimals: 9,
+            locked_amount: 500_000_000,
+            locked_decimals: 6,
+            collateral_usd: 100_000_000,
+            take_profit_price: price(take_profit_price),
+            stop_loss_price: price(stop_loss_price),
+            ..Position::default()
+        }
+    }
+
+    #[test]
+    fn test_get_trigger_status() {
+        let cases = [
+            // (side, take profit, stop loss, exit price, expected (take profit, stop loss) triggered)
+            (Side::Long, 120, 90, 125, (true, false)),
+            (Side::Long, 120, 90, 120, (true, false)),
+            (Side::Long, 120, 90, 100, (false, false)),
+            (Side::Long, 120, 90, 90, (false, true)),
+            (Side::Long, 120, 90, 85, (false, true)),
+            (Side::Long, 0, 0, 125, (false, false)),
+            (Side::Long, 0, 0, 85, (false, false)),
+            (Side::Short, 80, 110, 75, (true, false)),
+            (Side::Short, 80, 110, 80, (true, false)),
+            (Side::Short, 80, 110, 100, (false, false)),
+            (Side::Short, 80, 110, 110, (false, true)),
+            (Side::Short, 80, 110, 115, (false, true)),
+            (Side::Short, 0, 0, 75, (false, false)),
+            (Side::Short, 0, 0, 115, (false, false)),
+        ];
+
+        let pool = Pool::default();
+        let collateral_custody = Custody::default();
+        // 0.1% close fee
+        let target_custody = Custody {
+            fees: Fees {
+                close_position: 1_000_000,
+                ..Fees::default()
+            },
+            ..Custody::default()
+        };
+        for (side, take_profit_price, stop_loss_price, exit_price, (take_profit, stop_loss)) in cases.iter() {
+            let market = Market {
+                side: *side,
+                max_payoff_bps: 10_000,
+                ..Market::default()
+            };
+            let context = PositionContext {
+                pool: &pool,
+                market: &market,
+                target_custody: &target_custody,
+                collateral_custody: &collateral_custody,
+            };
+            let position = get_position(*take_profit_price, *stop_loss_price);
+            let status = get_trigger_status(&context, &position, &price(*exit_price), &price(1), 0).unwrap();
+
+            // A 20 USD move is worth 100 USD either way, a 10 USD one 50 USD, less the 0.5 USD fee
+            let expected = if *take_profit_price > 0 {
+                TriggerStatus {
+                    take_profit_triggered: *take_profit,
+                    stop_loss_triggered: *stop_loss,
+                    take_profit_payout_usd: 199_500_000,
+                    stop_loss_payout_usd: 49_500_000,
+                }
+            } else {
+                TriggerStatus::default()
+            };
+            assert_eq!(status, expected, "{:?} at {}", side, exit_price);
+        }
+    }
+
+    #[test]
+    fn test_validate_trigger_prices() {
+        let cases = [
+            // (side, take profit, stop loss, liquidation price, expected error)
+            (Side::Long, 120, 90, 81, None),
+            (Side::Long, 0, 0, 81, None),
+            (Side::Long, 0, 90, 0, None),
+            (Side::Long, 0, 70, 0, None),
+            (Side::Long, 100, 0, 81, Some(CompError::InvalidTakeProfitPrice)),
+            (Side::Long, 90, 0, 81, Some(CompError::InvalidTakeProfitPrice)),
+            (Side::Long, 0, 100, 81, Some(CompError::InvalidStopLossPrice)),
+            (Side::Long, 0, 81, 81, Some(CompError::InvalidStopLossPrice)),
+            (Side::Long, 0, 70, 81, Some(CompError::InvalidStopLossPrice)),
+            (Side::Short, 80, 110, 119, None),
+            (Side::Short, 0, 0, 119, None),
+            (Side::Short, 0, 130, 0, None),
+            (Side::Short, 100, 0, 119, Some(CompError::InvalidTakeProfitPrice)),
+            (Side::Short, 110, 0, 119, Some(CompError::InvalidTakeProfitPrice)),
+            (Side::Short, 0, 100, 119, Some(CompError::InvalidStopLossPrice)),
+            (Side::Short, 0, 119, 119, Some(CompError::InvalidStopLossPrice)),
+            (Side::Short, 0, 130, 119, Some(CompError::InvalidStopLossPrice)),
+        ];
+        for (side, take_profit_price, stop_loss_price, liquidation_price, expected) in cases.iter() {
+            let position = get_position(*take_profit_price, *stop_loss_price);
+            let result = validate_trigger_prices(&position, *side, &price(*liquidation_price));
+            match expected {
+                Some(err) => assert_eq!(result.unwrap_err(), (*err).into(), "{:?} {:?}", side, position),
+                None => assert!(result.is_ok(), "{:?} {:?}", side, position),
+            }
+        }
+    }
+}
@@ -0,0 +1,619 @@
+//! Pool valuation engine shared by the compute instructions and off-chain clients.
+
+use {
+    crate::{error::CompError, math, states::*},
+    anchor_lang::prelude::*,
+};
+
+/// Supplies the oracle price used to value a custody of the pool.
+pub trait PriceSource {
+    // Returns the price for `custody`, located at `custody_id` in `pool.custodies`
+    fn get_price(&self, custody_id: usize, custody: &Custody) -> Result<OraclePrice>;
+
+    // Returns the (min, max) price range for `custody`, as given by `fetch_from_oracle`
+    fn get_price_range(&self, custody_id: usize, custody: &Custody) -> Result<(OraclePrice, OraclePrice)> {
+        let price = self.get_price(custody_id, custody)?;
+        Ok((price, price))
+    }
+}
+
+impl PriceSource for [OraclePrice] {
+    fn get_price(&self, custody_id: usize, _custody: &Custody) -> Result<OraclePrice> {
+        self.get(custody_id)
+            .copied()
+            .ok_or_else(|| CompError::InvalidOraclePrice.into())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct CustodyPrice {
+    pub price: OraclePrice,
+    pub min_price: OraclePrice,
+    pub max_price: OraclePrice,
+}
+
+impl PriceSource for [CustodyPrice] {
+    fn get_price(&self, custody_id: usize, _custody: &Custody) -> Result<OraclePrice> {
+        self.get(custody_id)
+            .map(|price| price.price)
+            .ok_or_else(|| CompError::InvalidOraclePrice.into())
+    }
+
+    fn get_price_range(&self, custody_id: usize, _custody: &Custody) -> Result<(OraclePrice, OraclePrice)> {
+        self.get(custody_id)
+            .map(|price| (price.min_price, price.max_price))
+            .ok_or_else(|| CompError::InvalidOraclePrice.into())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Debug)]
+pub enum ValuationMode {
+    // Assets at min price, trader PnL at the price range end favouring traders
+    Conservative,
+    // Assets at max price, trader PnL at the price range end favouring the pool
+    Aggressive,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct PoolTokenPriceBounds {
+    pub min_sflp_price_usd: u64,
+    pub max_sflp_price_usd: u64,
+    pub min_flp_price: u64,
+    pub max_flp_price: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct MarketPnl {
+    // Unrealized profit of the market's traders, capped by the locked amount
+    pub profit_usd: u64,
+    // Unrealized loss of the market's traders, capped by their collateral
+    pub loss_usd: u64,
+}
+
+#[derive(Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct PoolValue {
+    // Value of the pool after obligations, collateral and unrealized PnL
+    pub equity_usd: u64,
+    // Value of the tokens owned by the pool
+    pub raw_aum_usd: u64,
+    // Value of the tokens owned by each custody, in `pool.custodies` order
+    pub custody_aum_usd: Vec<u64>,
+    // Unrealized PnL of each market, in `pool.markets` order
+    pub market_pnl: Vec<MarketPnl>,
+}
+
+pub struct PoolValuation<'a> {
+    pub pool: &'a Pool,
+    // Custody accounts in `pool.custodies` order
+    pub custodies: &'a [Custody],
+    // Market accounts in `pool.markets` order
+    pub markets: &'a [Market],
+}
+
+impl<'a> PoolValuation<'a> {
+    pub fn new(pool: &'a Pool, custodies: &'a [Custody], markets: &'a [Market]) -> Result<Self> {
+        if custodies.len() != pool.custodies.len() || markets.len() != pool.markets.len() {
+            return Err(ProgramError::NotEnoughAccountKeys.into());
+        }
+        Ok(Self {
+            pool,
+            custodies,
+            markets,
+        })
+    }
+
+    pub fn get_pool_value<P: PriceSource + ?Sized>(&self, prices: &P) -> Result<PoolValue> {
+        let mut custody_prices: Vec<OraclePrice> = Vec::with_capacity(self.custodies.len());
+        for (idx, custody) in self.custodies.iter().enumerate() {
+            custody_prices.push(prices.get_price(idx, custody)?);
+        }
+        self.value_pool(&custody_prices, &custody_prices, &custody_prices, false)
+    }
+
+    // Values the pool using the (min, max) price range of each custody
+    pub fn get_pool_value_in_mode<P: PriceSource + ?Sized>(
+        &self,
+        prices: &P,
+        mode: ValuationMode,
+    ) -> Result<PoolValue> {
+        let mut min_prices: Vec<OraclePrice> = Vec::with_capacity(self.custodies.len());
+        let mut max_prices: Vec<OraclePrice> = Vec::with_capacity(self.custodies.len());
+        for (idx, custody) in self.custodies.iter().enumerate() {
+            let (min_price, max_price) = prices.get_price_range(idx, custody)?;
+            min_prices.push(min_price);
+            max_prices.push(max_price);
+        }
+        match mode {
+            ValuationMode::Conservative => self.value_pool(&min_prices, &min_prices, &max_prices, true),
+            ValuationMode::Aggressive => self.value_pool(&max_prices, &min_prices, &max_prices, false),
+        }
+    }
+
+    // Values owned assets at asset_prices and trader PnL at either end of the
+    // [min_prices, max_prices] range, picking the end that favours traders if requested
+    fn value_pool(
+        &self,
+        asset_prices: &[OraclePrice],
+        min_prices: &[OraclePrice],
+        max_prices: &[OraclePrice],
+        favor_traders: bool,
+    ) -> Result<PoolValue> {
+        let mut custody_aum_usd: Vec<u64> = Vec::with_capacity(self.custodies.len());
+        let mut pool_equity: u64 = 0;
+
+        // Computing the raw AUM of the pool
+        for (idx, custody) in self.custodies.iter().enumerate() {
+            let token_amount_usd =
+                asset_prices[idx].get_asset_amount_usd(custody.assets.owned, custody.decimals)?;
+            custody_aum_usd.push(token_amount_usd);
+            pool_equity = math::checked_add(pool_equity, token_amount_usd)?;
+        }
+
+        let raw_aum_usd = pool_equity;
+
+        pool_equity = pool_equity.saturating_sub(math::checked_add(
+            self.pool.fees_obligation_usd,
+            self.pool.rebate_obligation_usd,
+        )?);
+
+        // Computing the unrealised PnL pending against the pool
+        let mut market_pnl: Vec<MarketPnl> = Vec::with_capacity(self.markets.len());
+        for market in self.markets.iter() {
+            let target_custody_id = self.pool.get_custody_id(&market.target_custody)?;
+            let collateral_custody_id = self.pool.get_custody_id(&market.collateral_custody)?;
+            // Longs gain from higher prices and shorts from lower ones
+            let exit_price = if (market.side == Side::Long) == favor_traders {
+                max_prices[target_custody_id]
+            } else {
+                min_prices[target_custody_id]
+            };
+            let collateral_price = if favor_traders {
+                max_prices[collateral_custody_id]
+            } else {
+                min_prices[collateral_custody_id]
+            };
+            // Get the collective position against the pool
+            let position = market.get_collective_position()?;
+            pool_equity = pool_equity.saturating_sub(position.collateral_usd);
+            let (profit_usd, loss_usd) = position.get_pnl_usd(
+                &exit_price,
+                market.side,
+                &collateral_price,
+                Perpetuals::BPS_POWER as u64,
+            )?;
+            let pnl = MarketPnl {
+                profit_usd,
+                loss_usd,
+            };
+
+            pool_equity = if profit_usd > 0 {
+                pool_equity.saturating_sub(profit_usd)
+            } else {
+                math::checked_add(pool_equity, loss_usd)?
+            };
+            market_pnl.push(pnl);
+        }
+
+        Ok(PoolValue {
+            equity_usd: pool_equity,
+            raw_aum_usd,
+            custody_aum_usd,
+            market_pnl,
+        })
+    }
+
+    // Returns (sflp_price_usd, flp_price) for the given supply of the staked LP mint
+    pub fn get_pool_token_prices<P: PriceSource + ?Sized>(
+        &self,
+        prices: &P,
+        lp_supply: u64,
+    ) -> Result<(u64, u64)> {
+        let pool_value = self.get_pool_value(prices)?;
+        self.get_pool_token_prices_from_equity(pool_value.equity_usd, lp_supply)
+    }
+
+    // Returns the LP token prices of the conservative and aggressive valuations, bounding
+    // the prices the pool mints and redeems at
+    pub fn get_pool_token_price_bounds<P: PriceSource + ?Sized>(
+        &self,
+        prices: &P,
+        lp_supply: u64,
+    ) -> Result<PoolTokenPriceBounds> {
+        let min_equity_usd = self
+            .get_pool_value_in_mode(prices, ValuationMode::Conservative)?
+            .equity_usd;
+        let max_equity_usd = self
+            .get_pool_value_in_mode(prices, ValuationMode::Aggressive)?
+            .equity_usd;
+
+        let (min_sflp_price_usd, min_flp_price) =
+            self.get_pool_token_prices_from_equity(min_equity_usd, lp_supply)?;
+        let (max_sflp_price_usd, max_flp_price) =
+            self.get_pool_token_prices_from_equity(max_equity_usd, lp_supply)?;
+
+        Ok(PoolTokenPriceBounds {
+            min_sflp_price_usd,
+            max_sflp_price_usd,
+            min_flp_price,
+            max_flp_price,
+        })
+    }
+
+    fn get_pool_token_prices_from_equity(&self, equity_usd: u64, lp_supply: u64) -> Result<(u64, u64)> {
+        let sflp_price_usd = math::checked_decimal_div(
+            equity_usd,
+            -(Perpetuals::USD_DECIMALS as i32),
+            lp_supply,
+            -(Perpetuals::LP_DECIMALS as i32),
+            -(Perpetuals::USD_DECIMALS as i32),
+        )?;
+
+        let compounding_factor = math::checked_decimal_div(
+            self.pool.compounding_stats.active_amount,
+            -(Perpetuals::LP_DECIMALS as i32),
+            self.pool.compounding_stats.total_supply,
+            -(Perpetuals::LP_DECIMALS as i32),
+            -(Perpetuals::LP_DECIMALS as i32),
+        )?;
+
+        let flp_price = math::checked_decimal_mul(
+            sflp_price_usd,
+            -(Perpetuals::USD_DECIMALS as i32),
+            compounding_factor,
+            -(Perpetuals::LP_DECIMALS as i32),
+            -(Perpetuals::USD_DECIMALS as i32),
+        )?;
+
+        Ok((sflp_price_usd, flp_price))
+    }
+}
+
+/// Off-chain equivalent of `flash_compute::get_pool_token_prices`.
+///
+/// `custodies` and `prices` follow `pool.custodies` order, `markets` follows `pool.markets`
+/// order and `lp_supply` is the supply of the pool's `lp_mint`.
+pub fn get_pool_token_prices(
+    pool: &Pool,
+    custodies: &[Custody],
+    markets: &[Market],
+    prices: &[OraclePrice],
+    lp_supply: u64,
+) -> Result<(u64, u64)> {
+    PoolValuation::new(pool, custodies, markets)?.get_pool_token_prices(prices, lp_supply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL_PRICE: u64 = 150_00000000;
+    const USDC_PRICE: u64 = 1_00000000;
+    const PRICE_EXPONENT: i32 = -8;
+
+    // Inline valuation loop of the original flash_compute::get_pool_token_prices
+    fn get_baseline_pool_token_prices(
+        pool: &Pool,
+        custodies: &[Custody],
+        markets: &[Market],
+        custody_prices: &[OraclePrice],
+        lp_supply: u64,
+    ) -> Result<(u64, u64)> {
+        let mut pool_equity: u64 = 0;
+        for (idx, custody) in custodies.iter().enumerate() {
+            let token_amount_usd =
+                custody_prices[idx].get_asset_amount_usd(custody.assets.owned, custody.decimals)?;
+            pool_equity = math::checked_add(pool_equity, token_amount_usd)?;
+        }
+
+        pool_equity = pool_equity.saturating_sub(math::checked_add(pool.fees_obligation_usd, pool.rebate_obligation_usd)?);
+
+        for market in markets.iter() {
+            let target_custody_id = pool.get_custody_id(&market.target_custody)?;
+            let collateral_custody_id = pool.get_custody_id(&market.collateral_custody)?;
+            let position = market.get_collective_position()?;
+            pool_equity = pool_equity.saturating_sub(position.collateral_usd);
+            let exit_price = custody_prices[target_custody_id];
+            pool_equity = if market.side == Side::Short {
+                if exit_price < position.entry_price {
+                    pool_equity.saturating_sub(std::cmp::min(
+                        position.entry_price.checked_sub(&exit_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
+                        custody_prices[collateral_custody_id].get_asset_amount_usd(position.locked_amount, position.locked_decimals)?
+                    ))
+                } else {
+                    pool_equity.checked_add(std::cmp::min(
+                        exit_price.checked_sub(&position.entry_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
+                        position.collateral_usd
+                    )).unwrap()
+                }
+            } else if exit_price > position.entry_price {
+                pool_equity.saturating_sub(std::cmp::min(
+                    exit_price.checked_sub(&position.entry_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
+                    custody_prices[collateral_custody_id].get_asset_amount_usd(position.locked_amount, position.locked_decimals)?
+                ))
+            } else {
+                pool_equity.checked_add(std::cmp::min(
+                    position.entry_price.checked_sub(&exit_price)?.get_asset_amount_usd(position.size_amount, position.size_decimals)?,
+                    position.collateral_usd
+                )).unwrap()
+            };
+        }
+
+        let sflp_price_usd = math::checked_decimal_div(
+            pool_equity,
+            -(Perpetuals::USD_DECIMALS as i32),
+            lp_supply,
+            -(Perpetuals::LP_DECIMALS as i32),
+            -(Perpetuals::USD_DECIMALS as i32),
+        )?;
+        let compounding_factor = math::checked_decimal_div(
+            pool.compounding_stats.active_amount,
+            -(Perpetuals::LP_DECIMALS as i32),
+            pool.compounding_stats.total_supply,
+            -(Perpetuals::LP_DECIMALS as i32),
+            -(Perpetuals::LP_DECIMALS as i32),
+        )?;
+        let flp_price = math::checked_decimal_mul(
+            sflp_price_usd,
+            -(Perpetuals::USD_DECIMALS as i32),
+            compounding_factor,
+            -(Perpetuals::LP_DECIMALS as i32),
+            -(Perpetuals::USD_DECIMALS as i32),
+        )?;
+        Ok((sflp_price_usd, flp_price))
+    }
+
+    struct MarketCase {
+        side: Side,
+        // Collateral in SOL (correlated) or USDC
+        sol_collateral: bool,
+        entry_price: u64,
+        size_amount: u64, // SOL, 9 decimals
+        locked_amount: u64, // collateral token
+        collateral_usd: u64,
+    }
+
+    fn get_fixtures(cases: &[MarketCase]) -> (Pool, Vec<Custody>, Vec<Market>, Vec<OraclePrice>) {
+        let sol_custody_key = Pubkey::new_unique();
+        let usdc_custody_key = Pubkey::new_unique();
+
+        let sol_custody = Custody {
+            decimals: 9,
+            assets: Assets {
+                owned: 10_000_000_000_000, // 10k SOL
+                ..Assets::default()
+            },
+            ..Custody::default()
+        };
+        let usdc_custody = Custody {
+            decimals: 6,
+            is_stable: true,
+            assets: Assets {
+                owned: 2_000_000_000_000, // 2M USDC
+                ..Assets::default()
+            },
+            ..Custody::default()
+        };
+
+        let markets: Vec<Market> = cases
+            .iter()
+            .map(|case| {
+                let (collateral_custody, locked_decimals) = if case.sol_collateral {
+                    (sol_custody_key, 9)
+                } else {
+                    (usdc_custody_key, 6)
+                };
+                Market {
+                    target_custody: sol_custody_key,
+                    collateral_custody,
+                    side: case.side,
+                    collective_position: PositionStats {
+                        open_positions: 3,
+                        average_entry_price: OraclePrice::new(case.entry_price, PRICE_EXPONENT),
+                        size_amount: case.size_amount,
+                        size_decimals: 9,
+                        locked_amount: case.locked_amount,
+                        locked_decimals,
+                        collateral_usd: case.collateral_usd,
+                        ..PositionStats::default()
+                    },
+                    ..Market::default()
+                }
+            })
+            .collect();
+
+        let pool = Pool {
+            custodies: vec![sol_custody_key, usdc_custody_key],
+            markets: markets.iter().map(|_| Pubkey::new_unique()).collect(),
+            fees_obligation_usd: 1_250_000_000,
+            rebate_obligation_usd: 250_000_000,
+            compounding_stats: CompoundingStats {
+                active_amount: 900_000_000_000,
+                total_supply: 750_000_000_000,
+                ..CompoundingStats::default()
+            },
+            ..Pool::default()
+        };
+
+        let prices = vec![
+            OraclePrice::new(SOL_PRICE, PRICE_EXPONENT),
+            OraclePrice::new(USDC_PRICE, PRICE_EXPONENT),
+        ];
+        (pool, vec![sol_custody, usdc_custody], markets, prices)
+    }
+
+    fn long_in_profit() -> MarketCase {
+        MarketCase {
+            side: Side::Long,
+            sol_collateral: true,
+            entry_price: 120_00000000,
+            size_amount: 1_000_000_000_000,
+            locked_amount: 1_000_000_000_000,
+            collateral_usd: 20_000_000_000,
+        }
+    }
+
+    fn long_in_loss() -> MarketCase {
+        MarketCase {
+            entry_price: 180_00000000,
+            ..long_in_profit()
+        }
+    }
+
+    fn long_capped_profit() -> MarketCase {
+        MarketCase {
+            entry_price: 50_00000000,
+            locked_amount: 10_000_000_000,
+            ..long_in_profit()
+        }
+    }
+
+    fn short_in_profit() -> MarketCase {
+        MarketCase {
+            side: Side::Short,
+            sol_collateral: false,
+            entry_price: 170_00000000,
+            size_amount: 500_000_000_000,
+            locked_amount: 85_000_000_000,
+            collateral_usd: 9_000_000_000,
+        }
+    }
+
+    fn short_in_loss() -> MarketCase {
+        MarketCase {
+            entry_price: 140_00000000,
+            ..short_in_profit()
+        }
+    }
+
+    fn short_capped_profit() -> MarketCase {
+        MarketCase {
+            entry_price: 300_00000000,
+            locked_amount: 1_000_000_000,
+            ..short_in_profit()
+        }
+    }
+
+    fn long_loss_over_collateral() -> MarketCase {
+        MarketCase {
+            entry_price: 400_00000000,
+            ..long_in_profit()
+        }
+    }
+
+    #[test]
+    fn test_get_pool_token_prices_matches_baseline() {
+        let cases: Vec<(&str, Vec<MarketCase>)> = vec![
+            ("no markets", vec![]),
+            ("long in profit", vec![long_in_profit()]),
+            ("long in loss", vec![long_in_loss()]),
+            ("long capped profit", vec![long_capped_profit()]),
+            ("long loss over collateral", vec![long_loss_over_collateral()]),
+            ("short in profit", vec![short_in_profit()]),
+            ("short in loss", vec![short_in_loss()]),
+            ("short capped profit", vec![short_capped_profit()]),
+            (
+                "mixed",
+                vec![long_in_profit(), short_in_loss(), long_capped_profit(), short_in_profit()],
+            ),
+        ];
+        let lp_supply = 1_500_000_000_000;
+
+        for (name, markets) in cases.iter() {
+            let (pool, custodies, markets, prices) = get_fixtures(markets);
+            let expected =
+                get_baseline_pool_token_prices(&pool, &custodies, &markets, &prices, lp_supply).unwrap();
+            let actual = get_pool_token_prices(&pool, &custodies, &markets, &prices, lp_supply).unwrap();
+            assert_eq!(actual, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_get_pool_value_caps_profit_by_locked_amount() {
+        let (pool, custodies, markets, prices) = get_fixtures(&[long_capped_profit(), short_capped_profit()]);
+        let pool_value = PoolValuation::new(&pool, &custodies, &markets)
+            .unwrap()
+            .get_pool_value(prices.as_slice())
+            .unwrap();
+
+        // 10 SOL locked at 150 USD, 1000 USDC locked
+        assert_eq!(pool_value.market_pnl[0], MarketPnl { profit_usd: 1_500_000_000, loss_usd: 0 });
+        assert_eq!(pool_value.market_pnl[1], MarketPnl { profit_usd: 1_000_000_000, loss_usd: 0 });
+    }
+
+    // SOL within 149-151 USD and USDC within 0.999-1.001 USD
+    fn get_custody_prices() -> Vec<CustodyPrice> {
+        let price = |min: u64, price: u64, max: u64| CustodyPrice {
+            price: OraclePrice::new(price, PRICE_EXPONENT),
+            min_price: OraclePrice::new(min, PRICE_EXPONENT),
+            max_price: OraclePrice::new(max, PRICE_EXPONENT),
+        };
+        vec![
+            price(149_00000000, SOL_PRICE, 151_00000000),
+            price(99_900000, USDC_PRICE, 1_00100000),
+        ]
+    }
+
+    #[test]
+    fn test_get_pool_token_price_bounds() {
+        let cases: Vec<(&str, Vec<MarketCase>)> = vec![
+            ("no markets", vec![]),
+            ("long in profit", vec![long_in_profit()]),
+            ("long in loss", vec![long_in_loss()]),
+            ("short in profit", vec![short_in_profit()]),
+            ("short in loss", vec![short_in_loss()]),
+            (
+                "mixed",
+                vec![long_in_profit(), short_in_loss(), long_capped_profit(), short_in_profit()],
+            ),
+        ];
+        let lp_supply = 1_500_000_000_000;
+        let prices = get_custody_prices();
+
+        for (name, markets) in cases.iter() {
+            let (pool, custodies, markets, _) = get_fixtures(markets);
+            let valuation = PoolValuation::new(&pool, &custodies, &markets).unwrap();
+            let (sflp_price_usd, flp_price) = valuation.get_pool_token_prices(prices.as_slice(), lp_supply).unwrap();
+            let bounds = valuation
+                .get_pool_token_price_bounds(prices.as_slice(), lp_supply)
+                .unwrap();
+
+            assert!(bounds.min_sflp_price_usd < bounds.max_sflp_price_usd, "{}", name);
+            assert!(bounds.min_sflp_price_usd <= sflp_price_usd, "{}", name);
+            assert!(sflp_price_usd <= bounds.max_sflp_price_usd, "{}", name);
+            assert!(bounds.min_flp_price <= flp_price && flp_price <= bounds.max_flp_price, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_get_pool_value_in_mode_favours_each_side() {
+        let (pool, custodies, markets, _) =
+            get_fixtures(&[long_in_profit(), short_in_profit(), short_in_loss()]);
+        let valuation = PoolValuation::new(&pool, &custodies, &markets).unwrap();
+        let prices = get_custody_prices();
+        let pnl = |mode| {
+            valuation
+                .get_pool_value_in_mode(prices.as_slice(), mode)
+                .unwrap()
+                .market_pnl
+        };
+
+        // 1000 SOL long from 120, 500 SOL shorts from 170 and 140: conservative valuations
+        // exit longs at 151 and shorts at 149, aggressive ones the other way around
+        assert_eq!(
+            pnl(ValuationMode::Conservative),
+            vec![
+                MarketPnl { profit_usd: 31_000_000_000, loss_usd: 0 },
+                MarketPnl { profit_usd: 10_500_000_000, loss_usd: 0 },
+                MarketPnl { profit_usd: 0, loss_usd: 4_500_000_000 },
+            ]
+        );
+        assert_eq!(
+            pnl(ValuationMode::Aggressive),
+            vec![
+                MarketPnl { profit_usd: 29_000_000_000, loss_usd: 0 },
+                MarketPnl { profit_usd: 9_500_000_000, loss_usd: 0 },
+                MarketPnl { profit_usd: 0, loss_usd: 5_500_000_000 },
+            ]
+        );
+    }
+}
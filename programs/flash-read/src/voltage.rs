@@ -0,0 +1,131 @@
+//! Voltage points earned from trading activity.
+
+use {
+    crate::{math, states::*},
+    anchor_lang::prelude::*,
+};
+
+// Activity of a trade, all amounts with implied USD_DECIMALS
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct VoltageActivity {
+    pub volume_usd: u64,
+    pub rewards_usd: u64,
+    pub rebates_usd: u64,
+}
+
+// Points have implied USD_DECIMALS
+#[derive(Copy, Clone, PartialEq, AnchorSerialize, AnchorDeserialize, Default, Debug)]
+pub struct VoltagePoints {
+    pub volume_points: u64,
+    pub rewards_points: u64,
+    pub rebates_points: u64,
+    pub total_points: u64,
+}
+
+fn apply_multiplier(amount_usd: u64, multiplier: u64) -> Result<u64> {
+    math::checked_as_u64(math::checked_div(
+        math::checked_mul(amount_usd as u128, multiplier as u128)?,
+        Perpetuals::RATE_POWER,
+    )?)
+}
+
+/// Returns the points of a single trade on `pool`.
+///
+/// Each activity is rewarded at its `VoltageMultiplier` rate (implied RATE_DECIMALS), with the
+/// volume also weighted by `pool.vp_volume_factor`, taken as a whole-number pool weight.
+pub fn get_voltage_points(
+    multiplier: &VoltageMultiplier,
+    pool: &Pool,
+    activity: &VoltageActivity,
+) -> Result<VoltagePoints> {
+    let volume_points = apply_multiplier(
+        math::checked_mul(activity.volume_usd, pool.vp_volume_factor as u64)?,
+        multiplier.volume,
+    )?;
+    let rewards_points = apply_multiplier(activity.rewards_usd, multiplier.rewards)?;
+    let rebates_points = apply_multiplier(activity.rebates_usd, multiplier.rebates)?;
+
+    Ok(VoltagePoints {
+        volume_points,
+        rewards_points,
+        rebates_points,
+        total_points: math::checked_add(
+            volume_points,
+            math::checked_add(rewards_points, rebates_points)?,
+        )?,
+    })
+}
+
+/// Sums the points of historical trades, each given with the pool it was made on.
+pub fn get_total_voltage_points(
+    multiplier: &VoltageMultiplier,
+    trades: &[(&Pool, VoltageActivity)],
+) -> Result<VoltagePoints> {
+    let mut total = VoltagePoints::default();
+    for (pool, activity) in trades.iter() {
+        let points = get_voltage_points(multiplier, pool, activity)?;
+        total.volume_points = math::checked_add(total.volume_points, points.volume_points)?;
+        total.rewards_points = math::checked_add(total.rewards_points, points.rewards_points)?;
+        total.rebates_points = math::checked_add(total.rebates_points, points.rebates_points)?;
+        total.total_points = math::checked_add(total.total_points, points.total_points)?;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MULTIPLIER: VoltageMultiplier = VoltageMultiplier {
+        volume: 2_000_000_000,
+        rewards: 500_000_000,
+        rebates: 1_000_000_000,
+    };
+
+    fn get_pool(vp_volume_factor: u8) -> Pool {
+        Pool {
+            vp_volume_factor,
+            ..Pool::default()
+        }
+    }
+
+    #[test]
+    fn test_get_voltage_points() {
+        let activity = VoltageActivity {
+            volume_usd: 10_000_000_000,
+            rewards_usd: 40_000_000,
+            rebates_usd: 5_000_000,
+        };
+        assert_eq!(
+            get_voltage_points(&MULTIPLIER, &get_pool(3), &activity).unwrap(),
+            VoltagePoints {
+                volume_points: 60_000_000_000,
+                rewards_points: 20_000_000,
+                rebates_points: 5_000_000,
+                total_points: 60_025_000_000,
+            }
+        );
+        // Pools without a volume factor don't reward volume
+        assert_eq!(
+            get_voltage_points(&MULTIPLIER, &get_pool(0), &activity)
+                .unwrap()
+                .total_points,
+            25_000_000
+        );
+    }
+
+    #[test]
+    fn test_get_total_voltage_points() {
+        let (pool_a, pool_b) = (get_pool(1), get_pool(2));
+        let activity = VoltageActivity {
+            volume_usd: 1_000_000_000,
+            rewards_usd: 2_000_000,
+            rebates_usd: 0,
+        };
+        let total = get_total_voltage_points(&MULTIPLIER, &[(&pool_a, activity), (&pool_b, activity)]).unwrap();
+        assert_eq!(total.volume_points, 6_000_000_000);
+        assert_eq!(total.rewards_points, 2_000_000);
+        assert_eq!(total.total_points, 6_002_000_000);
+        assert_eq!(get_total_voltage_points(&MULTIPLIER, &[]).unwrap(), VoltagePoints::default());
+    }
+}